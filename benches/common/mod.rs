@@ -0,0 +1,78 @@
+//! Timing harness shared by the benchmarks. Each one runs the same scripts
+//! under two sets of interpreter flags and prints the times side by side.
+
+use std::{
+    env, fs,
+    path::Path,
+    process::Command,
+    time::{Duration, Instant},
+};
+
+/// Recursive calls with little else going on.
+pub const FIB: (&str, &str) = (
+    "fib",
+    "
+fun fib(n) {
+  if (n < 2) return n;
+  return fib(n - 1) + fib(n - 2);
+}
+print fib(27);
+",
+);
+
+/// Each script is timed this many times and the fastest run kept.
+const RUNS: usize = 5;
+
+/// A column of the report: its heading and the flags it runs with.
+pub type Setup<'a> = (&'a str, &'a [&'a str]);
+
+/// Runs `script` with `flags` and returns its output and fastest time.
+fn time(script: &Path, flags: &[&str]) -> (String, Duration) {
+    let mut best = Duration::MAX;
+    let mut stdout = String::new();
+
+    for _ in 0..RUNS {
+        let started = Instant::now();
+        let output = Command::new(env!("CARGO_BIN_EXE_lox-rust-2"))
+            .args(flags)
+            .arg(script)
+            .output()
+            .expect("failed to run interpreter");
+        best = best.min(started.elapsed());
+
+        assert!(
+            output.status.success(),
+            "{} failed with {flags:?}",
+            script.display()
+        );
+        stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+    }
+
+    (stdout, best)
+}
+
+/// Times every script under `slow` and `fast`, checks both print the same
+/// output and reports how much faster `fast` is.
+pub fn compare(scripts: &[(&str, &str)], slow: Setup, fast: Setup) {
+    println!(
+        "{:<8} {:>10} {:>10} {:>8}",
+        "script", slow.0, fast.0, "speedup"
+    );
+
+    for (name, source) in scripts {
+        let path = env::temp_dir().join(format!("lox-bench-{name}-{}.lox", std::process::id()));
+        fs::write(&path, source).expect("failed to write benchmark script");
+
+        let (slow_output, slow_time) = time(&path, slow.1);
+        let (fast_output, fast_time) = time(&path, fast.1);
+        fs::remove_file(&path).ok();
+
+        assert_eq!(slow_output, fast_output, "{name} printed different output");
+        println!(
+            "{name:<8} {:>9.3}s {:>9.3}s {:>7.2}x",
+            slow_time.as_secs_f64(),
+            fast_time.as_secs_f64(),
+            slow_time.as_secs_f64() / fast_time.as_secs_f64()
+        );
+    }
+}
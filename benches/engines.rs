@@ -0,0 +1,66 @@
+//! Times the same scripts on the stack engine and on the register engine.
+//! Run with `cargo +nightly bench --bench engines`.
+
+mod common;
+
+const SCRIPTS: &[(&str, &str)] = &[
+    (
+        "locals",
+        "
+fun main() {
+  var i = 0;
+  var total = 0;
+  while (i < 3000000) {
+    total = total + i * 2 - 1;
+    i = i + 1;
+  }
+  print total;
+}
+main();
+",
+    ),
+    (
+        "globals",
+        "
+var i = 0;
+var total = 0;
+while (i < 2000000) {
+  total = total + i;
+  i = i + 1;
+}
+print total;
+",
+    ),
+    (
+        "closure",
+        "
+fun counter() {
+  var count = 0;
+  fun step() {
+    count = count + 1;
+    return count;
+  }
+  return step;
+}
+fun main() {
+  var step = counter();
+  var i = 0;
+  while (i < 1000000) {
+    step();
+    i = i + 1;
+  }
+  print step();
+}
+main();
+",
+    ),
+    common::FIB,
+];
+
+fn main() {
+    common::compare(
+        SCRIPTS,
+        ("stack", &["--engine", "stack"]),
+        ("register", &["--engine", "register"]),
+    );
+}
@@ -0,0 +1,56 @@
+//! Times loops over local variables at `-O0` and `-O1`, the level that
+//! compiles them to superinstructions. Run with `cargo +nightly bench`.
+
+mod common;
+
+/// Each loop runs inside a function, so its variables are locals.
+const SCRIPTS: &[(&str, &str)] = &[
+    (
+        "count",
+        "
+fun main() {
+  var i = 0;
+  while (i < 5000000) {
+    i = i + 1;
+  }
+  print i;
+}
+main();
+",
+    ),
+    (
+        "sum",
+        "
+fun main() {
+  var i = 0;
+  var total = 0;
+  while (i < 3000000) {
+    total = total + i;
+    i = i + 1;
+  }
+  print total;
+}
+main();
+",
+    ),
+    (
+        "offset",
+        "
+fun main() {
+  var i = 0;
+  var last = 0;
+  while (i < 3000000) {
+    last = i + 2;
+    i = i + 1;
+  }
+  print last;
+}
+main();
+",
+    ),
+    common::FIB,
+];
+
+fn main() {
+    common::compare(SCRIPTS, ("-O0", &["-O0"]), ("-O1", &["-O1"]));
+}
@@ -1,6 +1,16 @@
 use clap::Parser;
 
-#[derive(Parser, Debug)]
+/// Which engine runs the compiled program.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum Engine {
+    /// The stack machine the bytecode is written for.
+    Stack,
+    /// Lowers each function to three-address code over its frame slots.
+    /// Functions the lowering doesn't handle run on the stack machine.
+    Register,
+}
+
+#[derive(Parser, Debug, Clone)]
 #[command(version, about, long_about = None)]
 pub struct Args {
     #[arg(short, long, default_value_t = log::LevelFilter::Info)]
@@ -9,5 +19,27 @@ pub struct Args {
     #[arg(short, long, default_value_t = false)]
     pub disassemble: bool,
 
+    /// Compile `assert` statements out of the program.
+    #[arg(long, default_value_t = false)]
+    pub no_asserts: bool,
+
+    /// Let the event loop jump straight to the next timer instead of sleeping.
+    #[arg(long, default_value_t = false)]
+    pub virtual_clock: bool,
+
+    /// Run the garbage collector at every instruction that follows an
+    /// allocation, to shake out objects the VM forgot to root.
+    #[arg(long, default_value_t = false)]
+    pub gc_stress: bool,
+
+    /// Optimization level: 0 runs the bytecode as the parser emitted it, 1
+    /// folds constants and rewrites it with peephole passes.
+    #[arg(short = 'O', default_value_t = 1, value_parser = clap::value_parser!(u8).range(0..=1))]
+    pub opt_level: u8,
+
+    /// Which engine runs the program.
+    #[arg(long, value_enum, default_value_t = Engine::Stack)]
+    pub engine: Engine,
+
     pub source: Option<String>,
 }
@@ -0,0 +1,86 @@
+use std::collections::HashMap;
+
+use crate::{function::Closure, heap::Handle, value::Value};
+
+#[derive(Debug, Clone)]
+pub struct Class {
+    pub name: String,
+    pub methods: HashMap<String, Handle<Closure>>,
+    pub getters: HashMap<String, Handle<Closure>>,
+    pub setters: HashMap<String, Handle<Closure>>,
+    pub static_methods: HashMap<String, Handle<Closure>>,
+    pub static_fields: HashMap<String, Value>,
+    pub traits: Vec<Handle<Trait>>,
+}
+
+impl Class {
+    pub fn new(name: String) -> Self {
+        Self {
+            name,
+            methods: HashMap::new(),
+            getters: HashMap::new(),
+            setters: HashMap::new(),
+            static_methods: HashMap::new(),
+            static_fields: HashMap::new(),
+            traits: vec![],
+        }
+    }
+
+    pub fn find_method(&self, name: &str) -> Option<Handle<Closure>> {
+        self.methods.get(name).copied()
+    }
+
+    pub fn find_getter(&self, name: &str) -> Option<Handle<Closure>> {
+        self.getters.get(name).copied()
+    }
+
+    pub fn find_setter(&self, name: &str) -> Option<Handle<Closure>> {
+        self.setters.get(name).copied()
+    }
+
+    pub fn implements(&self, other: Handle<Trait>) -> bool {
+        self.traits.contains(&other)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Trait {
+    pub name: String,
+    pub methods: HashMap<String, Handle<Closure>>,
+}
+
+impl Trait {
+    pub fn new(name: String) -> Self {
+        Self {
+            name,
+            methods: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Instance {
+    pub class: Handle<Class>,
+    pub fields: HashMap<String, Value>,
+}
+
+impl Instance {
+    pub fn new(class: Handle<Class>) -> Self {
+        Self {
+            class,
+            fields: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BoundMethod {
+    pub receiver: Value,
+    pub method: Handle<Closure>,
+}
+
+impl BoundMethod {
+    pub fn new(receiver: Value, method: Handle<Closure>) -> Self {
+        Self { receiver, method }
+    }
+}
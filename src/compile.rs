@@ -1,14 +1,20 @@
-use log::trace;
-
+use crate::args::Args;
 use crate::error::LoxError;
 use crate::function::Function;
+use crate::globals::Globals;
+use crate::interner::Interner;
 use crate::lexer::Lexer;
 use crate::parser::Parser;
 
-pub fn compile<'a>(source: &'a str) -> Result<Function, LoxError> {
-    trace!("compile::compile(source)");
+pub fn compile<'a>(
+    source: &'a str,
+    args: &Args,
+    interner: &'a mut Interner,
+    globals: &'a mut Globals,
+) -> Result<Function, LoxError> {
+    trace!("compile::compile(source, args: {:?})", args);
     let mut lexer = Lexer::new(source);
-    let mut parser = Parser::new(source, lexer.iter().peekable());
+    let mut parser = Parser::new(source, lexer.iter().peekable(), args, interner, globals);
     let frame = parser.parse()?;
 
     Ok(frame)
@@ -1,4 +1,7 @@
-use crate::vm::{Chunk, OpCode};
+use crate::function::Function;
+use crate::register::{BinaryOp, Instruction, Operand, UnaryOp};
+use crate::value::Value;
+use crate::vm::{Chunk, OpCode, UPVALUE_LOCAL, UPVALUE_LONG, decode_operand};
 
 pub fn disassemble_chunk(name: &str, chunk: &Chunk) {
     println!("===   {name}   ===");
@@ -15,8 +18,8 @@ pub fn disassemble_chunk(name: &str, chunk: &Chunk) {
 }
 
 pub fn disassemble_instruction(chunk: &Chunk, offset: usize) -> usize {
-    let instruction = OpCode::from_usize(chunk.instructions[offset]);
-    let loc = &chunk.loc[offset];
+    let instruction = OpCode::from_u8(chunk.instructions[offset]);
+    let loc = chunk.loc(offset);
 
     print!("{offset:0>4} [{}:{}] ", loc.line, loc.col);
 
@@ -35,27 +38,75 @@ pub fn disassemble_instruction(chunk: &Chunk, offset: usize) -> usize {
         OpCode::Equal => simple_instruction("OP_EQUAL", offset),
         OpCode::Greater => simple_instruction("OP_GREATER", offset),
         OpCode::Less => simple_instruction("OP_LESS", offset),
-        OpCode::Print => simple_instruction("OP_PRINT", offset),
+        OpCode::Print => byte_instruction("OP_PRINT", chunk, offset),
         OpCode::Pop => simple_instruction("OP_POP", offset),
-        OpCode::DefineGlobal => constant_instruction("OP_DEFINE_GLOBAL", &chunk, offset),
-        OpCode::GetGlobal => constant_instruction("OP_GET_GLOBAL", &chunk, offset),
-        OpCode::SetGlobal => constant_instruction("OP_SET_GLOBAL", &chunk, offset),
+        OpCode::DefineGlobal => byte_instruction("OP_DEFINE_GLOBAL", &chunk, offset),
+        OpCode::GetGlobal => byte_instruction("OP_GET_GLOBAL", &chunk, offset),
+        OpCode::SetGlobal => byte_instruction("OP_SET_GLOBAL", &chunk, offset),
         OpCode::GetLocal => byte_instruction("OP_GET_LOCAL", &chunk, offset),
         OpCode::SetLocal => byte_instruction("OP_SET_LOCAL", &chunk, offset),
         OpCode::JumpIfFalse => jump_instruction("OP_JUMP_IF_FALSE", &chunk, 1, offset),
         OpCode::Jump => jump_instruction("OP_JUMP", &chunk, 1, offset),
         OpCode::Loop => jump_instruction("OP_LOOP", &chunk, -1, offset),
         OpCode::Call => byte_instruction("OP_CALL", &chunk, offset),
-        OpCode::Closure => {
-            let constant = chunk.instructions[offset + 1];
-            print!("  OP_CLOSURE {constant:0>4}");
-            let value = chunk.constants[constant].clone();
-            println!(" {}", value);
-            offset + 2
-        }
+        OpCode::Closure => closure_instruction("OP_CLOSURE", chunk, 1, offset),
         OpCode::GetUpvalue => byte_instruction("OP_GET_UPVALUE", &chunk, offset),
         OpCode::SetUpvalue => byte_instruction("OP_SET_UPVALUE", &chunk, offset),
         OpCode::CloseUpValue => simple_instruction("OP_CLOSE_UPVALUE", offset),
+        OpCode::GetProperty => constant_instruction("OP_GET_PROPERTY", chunk, offset),
+        OpCode::MatchVariant => byte_instruction("OP_MATCH_VARIANT", chunk, offset),
+        OpCode::Destructure => simple_instruction("OP_DESTRUCTURE", offset),
+        OpCode::Class => constant_instruction("OP_CLASS", chunk, offset),
+        OpCode::Method => constant_instruction("OP_METHOD", chunk, offset),
+        OpCode::SetProperty => constant_instruction("OP_SET_PROPERTY", chunk, offset),
+        OpCode::GetIndex => simple_instruction("OP_GET_INDEX", offset),
+        OpCode::SetIndex => simple_instruction("OP_SET_INDEX", offset),
+        OpCode::Getter => constant_instruction("OP_GETTER", chunk, offset),
+        OpCode::Setter => constant_instruction("OP_SETTER", chunk, offset),
+        OpCode::StaticMethod => constant_instruction("OP_STATIC_METHOD", chunk, offset),
+        OpCode::StaticField => constant_instruction("OP_STATIC_FIELD", chunk, offset),
+        OpCode::Trait => constant_instruction("OP_TRAIT", chunk, offset),
+        OpCode::Implement => simple_instruction("OP_IMPLEMENT", offset),
+        OpCode::Is => simple_instruction("OP_IS", offset),
+        OpCode::JumpIfNil => jump_instruction("OP_JUMP_IF_NIL", chunk, 1, offset),
+        OpCode::TailCall => byte_instruction("OP_TAIL_CALL", chunk, offset),
+        OpCode::Defer => simple_instruction("OP_DEFER", offset),
+        OpCode::BuildList => byte_instruction("OP_BUILD_LIST", chunk, offset),
+        OpCode::ExtendList => byte_instruction("OP_EXTEND_LIST", chunk, offset),
+        OpCode::Spread => simple_instruction("OP_SPREAD", offset),
+        OpCode::CallSpread => simple_instruction("OP_CALL_SPREAD", offset),
+        OpCode::TailCallSpread => simple_instruction("OP_TAIL_CALL_SPREAD", offset),
+        OpCode::Assert => constant_instruction("OP_ASSERT", chunk, offset),
+        OpCode::PrintWith => byte_instruction("OP_PRINT_WITH", chunk, offset),
+        OpCode::Await => simple_instruction("OP_AWAIT", offset),
+        OpCode::ConstantLong => long_constant_instruction("OP_CONSTANT_LONG", chunk, offset),
+        OpCode::DefineGlobalLong => long_instruction("OP_DEFINE_GLOBAL_LONG", chunk, offset),
+        OpCode::GetGlobalLong => long_instruction("OP_GET_GLOBAL_LONG", chunk, offset),
+        OpCode::SetGlobalLong => long_instruction("OP_SET_GLOBAL_LONG", chunk, offset),
+        OpCode::GetLocalLong => long_instruction("OP_GET_LOCAL_LONG", chunk, offset),
+        OpCode::SetLocalLong => long_instruction("OP_SET_LOCAL_LONG", chunk, offset),
+        OpCode::ClosureLong => closure_instruction("OP_CLOSURE_LONG", chunk, 3, offset),
+        OpCode::GetUpvalueLong => long_instruction("OP_GET_UPVALUE_LONG", chunk, offset),
+        OpCode::SetUpvalueLong => long_instruction("OP_SET_UPVALUE_LONG", chunk, offset),
+        OpCode::GetPropertyLong => long_constant_instruction("OP_GET_PROPERTY_LONG", chunk, offset),
+        OpCode::SetPropertyLong => long_constant_instruction("OP_SET_PROPERTY_LONG", chunk, offset),
+        OpCode::ClassLong => long_constant_instruction("OP_CLASS_LONG", chunk, offset),
+        OpCode::MethodLong => long_constant_instruction("OP_METHOD_LONG", chunk, offset),
+        OpCode::GetterLong => long_constant_instruction("OP_GETTER_LONG", chunk, offset),
+        OpCode::SetterLong => long_constant_instruction("OP_SETTER_LONG", chunk, offset),
+        OpCode::StaticMethodLong => {
+            long_constant_instruction("OP_STATIC_METHOD_LONG", chunk, offset)
+        }
+        OpCode::StaticFieldLong => long_constant_instruction("OP_STATIC_FIELD_LONG", chunk, offset),
+        OpCode::TraitLong => long_constant_instruction("OP_TRAIT_LONG", chunk, offset),
+        OpCode::AssertLong => long_constant_instruction("OP_ASSERT_LONG", chunk, offset),
+        OpCode::NotEqual => simple_instruction("OP_NOT_EQUAL", offset),
+        OpCode::GreaterEqual => simple_instruction("OP_GREATER_EQUAL", offset),
+        OpCode::LessEqual => simple_instruction("OP_LESS_EQUAL", offset),
+        OpCode::AddLocalConst => local_constant_instruction("OP_ADD_LOCAL_CONST", chunk, offset),
+        OpCode::IncrLocal => local_constant_instruction("OP_INCR_LOCAL", chunk, offset),
+        OpCode::GetLocal2 => two_byte_instruction("OP_GET_LOCAL2", chunk, offset),
+        OpCode::JumpIfLessLocal => jump_if_less_local_instruction(chunk, offset),
         OpCode::Unknown => {
             println!("Unknown opcode {:?}", instruction);
             offset + 1
@@ -68,27 +119,228 @@ pub fn simple_instruction(instruction: &str, offset: usize) -> usize {
     offset + 1
 }
 
+/// Reads the big-endian operand of `width` bytes at `offset`.
+fn read_operand(chunk: &Chunk, offset: usize, width: usize) -> usize {
+    decode_operand(&chunk.instructions[offset..offset + width])
+}
+
 pub fn constant_instruction(instruction: &str, chunk: &Chunk, offset: usize) -> usize {
-    let constant = chunk.instructions[offset + 1];
+    let constant = read_operand(chunk, offset + 1, 1);
     let value = &chunk.constants[constant];
 
     println!("{instruction} {constant:0>4} {}", value);
     offset + 2
 }
 
+pub fn long_constant_instruction(instruction: &str, chunk: &Chunk, offset: usize) -> usize {
+    let constant = read_operand(chunk, offset + 1, 3);
+    let value = &chunk.constants[constant];
+
+    println!("{instruction} {constant:0>4} {}", value);
+    offset + 4
+}
+
 pub fn byte_instruction(instruction: &str, chunk: &Chunk, offset: usize) -> usize {
-    let slot = chunk.instructions[offset + 1];
+    let slot = read_operand(chunk, offset + 1, 1);
 
     println!("{instruction} {slot:0>4}");
     offset + 2
 }
 
+pub fn long_instruction(instruction: &str, chunk: &Chunk, offset: usize) -> usize {
+    let slot = read_operand(chunk, offset + 1, 3);
+
+    println!("{instruction} {slot:0>4}");
+    offset + 4
+}
+
+/// For superinstructions taking a local slot and a constant.
+pub fn local_constant_instruction(instruction: &str, chunk: &Chunk, offset: usize) -> usize {
+    let slot = read_operand(chunk, offset + 1, 1);
+    let constant = read_operand(chunk, offset + 2, 1);
+    let value = &chunk.constants[constant];
+
+    println!("{instruction} {slot:0>4} {constant:0>4} {}", value);
+    offset + 3
+}
+
+pub fn two_byte_instruction(instruction: &str, chunk: &Chunk, offset: usize) -> usize {
+    let a = read_operand(chunk, offset + 1, 1);
+    let b = read_operand(chunk, offset + 2, 1);
+
+    println!("{instruction} {a:0>4} {b:0>4}");
+    offset + 3
+}
+
+pub fn jump_if_less_local_instruction(chunk: &Chunk, offset: usize) -> usize {
+    let slot = read_operand(chunk, offset + 1, 1);
+    let constant = read_operand(chunk, offset + 2, 1);
+    let jump = read_operand(chunk, offset + 3, 2);
+    let value = &chunk.constants[constant];
+
+    println!(
+        "OP_JUMP_IF_LESS_LOCAL {slot:0>4} {constant:0>4} {} {offset} -> {}",
+        value,
+        offset + 5 + jump
+    );
+    offset + 5
+}
+
 pub fn jump_instruction(instruction: &str, chunk: &Chunk, sign: i64, offset: usize) -> usize {
-    let jump = chunk.instructions[offset + 1];
+    let jump = read_operand(chunk, offset + 1, 2);
 
     println!(
         "{instruction} {offset} -> {}",
-        (offset as i64) + 2 + sign * (jump as i64)
+        (offset as i64) + 3 + sign * (jump as i64)
     );
-    offset + 2
+    offset + 3
+}
+
+/// Prints the closure's function and the upvalue descriptors following it.
+pub fn closure_instruction(instruction: &str, chunk: &Chunk, width: usize, offset: usize) -> usize {
+    let constant = read_operand(chunk, offset + 1, width);
+    let value = &chunk.constants[constant];
+    println!("{instruction} {constant:0>4} {}", value);
+
+    let mut offset = offset + 1 + width;
+    let upvalue_count = match value {
+        Value::Function { value } => value.upvalue_count,
+        _ => 0,
+    };
+    for _ in 0..upvalue_count {
+        let flags = chunk.instructions[offset];
+        let width = if flags & UPVALUE_LONG != 0 { 3 } else { 1 };
+        let index = read_operand(chunk, offset + 1, width);
+        let kind = if flags & UPVALUE_LOCAL != 0 {
+            "local"
+        } else {
+            "upvalue"
+        };
+
+        println!("{offset:0>4}    |                     {kind} {index}");
+        offset += 1 + width;
+    }
+
+    offset
+}
+
+/// Prints the register code of `function` and of the functions in its
+/// constants, innermost first as the parser prints their bytecode.
+pub fn disassemble_registers(function: &Function) {
+    for constant in function.chunk.constants.iter() {
+        if let Value::Function { value } = constant {
+            disassemble_registers(value);
+        }
+    }
+
+    let name = function.name.as_deref().unwrap_or("<script>");
+    let Some(code) = &function.register_code else {
+        println!("=== {name} runs on the stack engine ===");
+        return;
+    };
+
+    println!("===   {name} registers   ===");
+    for pc in 0..code.instructions.len() {
+        disassemble_register_instruction(function, pc);
+    }
+    println!("=== {name} registers end ===");
+}
+
+pub fn disassemble_register_instruction(function: &Function, pc: usize) {
+    let Some(code) = &function.register_code else {
+        return;
+    };
+    let constants = &function.chunk.constants;
+    let loc = function.chunk.loc(code.offsets[pc] - 1);
+
+    print!("{pc:0>4} [{}:{}] ", loc.line, loc.col);
+
+    let operand = |operand: &Operand| match operand {
+        Operand::Register(register) => format!("r{register}"),
+        Operand::Constant(index) => format!("k{index} ({})", constants[*index]),
+        Operand::Nil => String::from("nil"),
+        Operand::Bool(value) => value.to_string(),
+    };
+
+    match &code.instructions[pc] {
+        Instruction::Move { dst, src } => println!("R_MOVE r{dst}, {}", operand(src)),
+        Instruction::Unary { op, dst, src } => {
+            let name = match op {
+                UnaryOp::Negate => "R_NEGATE",
+                UnaryOp::Not => "R_NOT",
+            };
+            println!("{name} r{dst}, {}", operand(src));
+        }
+        Instruction::Binary { op, dst, a, b } => {
+            let name = match op {
+                BinaryOp::Add => "R_ADD",
+                BinaryOp::Subtract => "R_SUBTRACT",
+                BinaryOp::Multiply => "R_MULTIPLY",
+                BinaryOp::Divide => "R_DIVIDE",
+                BinaryOp::Equal => "R_EQUAL",
+                BinaryOp::NotEqual => "R_NOT_EQUAL",
+                BinaryOp::Greater => "R_GREATER",
+                BinaryOp::GreaterEqual => "R_GREATER_EQUAL",
+                BinaryOp::Less => "R_LESS",
+                BinaryOp::LessEqual => "R_LESS_EQUAL",
+            };
+            println!("{name} r{dst}, {}, {}", operand(a), operand(b));
+        }
+        Instruction::Jump { target } => println!("R_JUMP -> {target:0>4}"),
+        Instruction::JumpIfFalse { cond, target } => {
+            println!("R_JUMP_IF_FALSE r{cond} -> {target:0>4}")
+        }
+        Instruction::DefineGlobal { slot, src } => {
+            println!("R_DEFINE_GLOBAL {slot:0>4}, {}", operand(src))
+        }
+        Instruction::GetGlobal { dst, slot } => println!("R_GET_GLOBAL r{dst}, {slot:0>4}"),
+        Instruction::SetGlobal { slot, src } => {
+            println!("R_SET_GLOBAL {slot:0>4}, {}", operand(src))
+        }
+        Instruction::GetUpvalue { dst, index } => println!("R_GET_UPVALUE r{dst}, {index}"),
+        Instruction::SetUpvalue { index, src } => {
+            println!("R_SET_UPVALUE {index}, {}", operand(src))
+        }
+        Instruction::GetProperty {
+            dst,
+            object,
+            constant,
+        } => println!(
+            "R_GET_PROPERTY r{dst}, {}, {}",
+            operand(object),
+            operand(&Operand::Constant(*constant))
+        ),
+        Instruction::SetProperty {
+            dst,
+            object,
+            value,
+            constant,
+        } => println!(
+            "R_SET_PROPERTY r{dst}, {}, {}, {}",
+            operand(object),
+            operand(&Operand::Constant(*constant)),
+            operand(value)
+        ),
+        Instruction::CloseUpvalue { register } => println!("R_CLOSE_UPVALUE r{register}"),
+        Instruction::Closure {
+            dst,
+            constant,
+            captures,
+        } => {
+            println!(
+                "R_CLOSURE r{dst}, {}",
+                operand(&Operand::Constant(*constant))
+            );
+            for (local, index) in captures {
+                let kind = if *local { "local" } else { "upvalue" };
+                println!("     |                     {kind} {index}");
+            }
+        }
+        Instruction::Call { callee, arg_count } => println!("R_CALL r{callee}, {arg_count}"),
+        Instruction::TailCall { callee, arg_count } => {
+            println!("R_TAIL_CALL r{callee}, {arg_count}")
+        }
+        Instruction::Print { start, count } => println!("R_PRINT r{start}, {count}"),
+        Instruction::Return { src } => println!("R_RETURN {}", operand(src)),
+    }
 }
@@ -0,0 +1,76 @@
+use std::rc::Rc;
+
+use crate::value::Value;
+
+#[derive(Debug, Clone)]
+pub struct EnumVariant {
+    pub name: String,
+    pub arity: usize,
+}
+
+impl EnumVariant {
+    pub fn new(name: String, arity: usize) -> Self {
+        Self { name, arity }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Enum {
+    pub name: String,
+    pub variants: Vec<EnumVariant>,
+}
+
+impl Enum {
+    pub fn new(name: String, variants: Vec<EnumVariant>) -> Self {
+        Self { name, variants }
+    }
+
+    pub fn find_variant(&self, name: &str) -> Option<usize> {
+        self.variants
+            .iter()
+            .position(|variant| variant.name == name)
+    }
+}
+
+/// Callable produced by `Shape.Circle` for variants that carry a payload.
+#[derive(Debug, Clone)]
+pub struct VariantConstructor {
+    pub enum_def: Rc<Enum>,
+    pub variant: usize,
+}
+
+impl VariantConstructor {
+    pub fn new(enum_def: Rc<Enum>, variant: usize) -> Self {
+        Self { enum_def, variant }
+    }
+
+    pub fn arity(&self) -> usize {
+        self.enum_def.variants[self.variant].arity
+    }
+}
+
+/// Tagged enum value together with its payload.
+#[derive(Debug, Clone)]
+pub struct Variant {
+    pub enum_def: Rc<Enum>,
+    pub variant: usize,
+    pub payload: Vec<Value>,
+}
+
+impl Variant {
+    pub fn new(enum_def: Rc<Enum>, variant: usize, payload: Vec<Value>) -> Self {
+        Self {
+            enum_def,
+            variant,
+            payload,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.enum_def.variants[self.variant].name
+    }
+
+    pub fn is_same_variant(&self, enum_def: &Rc<Enum>, variant: usize) -> bool {
+        Rc::ptr_eq(&self.enum_def, enum_def) && self.variant == variant
+    }
+}
@@ -3,10 +3,23 @@ use std::fmt;
 
 #[derive(Debug, Clone)]
 pub enum LexerError {
-    UnexpectedCharacter { line: usize, col: usize },
-    UnterminatedString { line: usize, col: usize },
-    InvalidNumber { line: usize, col: usize },
-    CommentNotTerminated { line: usize, col: usize },
+    UnexpectedCharacter {
+        line: usize,
+        col: usize,
+    },
+    UnterminatedString {
+        line: usize,
+        col: usize,
+    },
+    InvalidNumber {
+        line: usize,
+        col: usize,
+        reason: String,
+    },
+    CommentNotTerminated {
+        line: usize,
+        col: usize,
+    },
 }
 
 impl fmt::Display for LexerError {
@@ -18,8 +31,8 @@ impl fmt::Display for LexerError {
             Self::UnterminatedString { line: _, col: _ } => {
                 write!(f, "Unterminated string")
             }
-            Self::InvalidNumber { line: _, col: _ } => {
-                write!(f, "Invalid number")
+            Self::InvalidNumber { line, col, reason } => {
+                write!(f, "[{line}:{col}] Invalid number: {reason}")
             }
             Self::CommentNotTerminated { line: _, col: _ } => {
                 write!(f, "Comment is not terminated")
@@ -40,6 +53,7 @@ impl LexerError {
 pub enum ParserError {
     UnexpectedToken { line: usize, col: usize },
     OutOfSourceBoundary,
+    HadErrors,
 }
 
 impl fmt::Display for ParserError {
@@ -47,6 +61,7 @@ impl fmt::Display for ParserError {
         match self {
             Self::UnexpectedToken { line, col } => write!(f, "[{line}:{col}] Unexpected token"),
             Self::OutOfSourceBoundary => write!(f, "Out of source boundary"),
+            Self::HadErrors => write!(f, "Compilation failed"),
         }
     }
 }
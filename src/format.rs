@@ -0,0 +1,230 @@
+use std::{iter::Peekable, str::Chars};
+
+/// An argument to `format`. Anything but a number is rendered to text
+/// beforehand, so the caller can run `toString` methods.
+pub enum Arg {
+    Number(f64),
+    Text(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Align {
+    Left,
+    Center,
+    Right,
+}
+
+impl Align {
+    fn from_char(c: char) -> Option<Self> {
+        match c {
+            '<' => Some(Self::Left),
+            '^' => Some(Self::Center),
+            '>' => Some(Self::Right),
+            _ => None,
+        }
+    }
+}
+
+/// Largest width or precision a format spec may ask for, so a typo can't
+/// allocate gigabytes of padding.
+const MAX_SPEC_NUMBER: usize = 1000;
+
+/// Parsed `[[fill]align][+][0][width][.precision]` part of a placeholder,
+/// following the subset of Rust's `format!` syntax that makes sense for Lox
+/// values.
+#[derive(Debug)]
+struct FormatSpec {
+    fill: char,
+    align: Option<Align>,
+    sign: bool,
+    zero: bool,
+    width: usize,
+    precision: Option<usize>,
+}
+
+impl FormatSpec {
+    fn parse(spec: &str) -> Result<Self, String> {
+        trace!("format::FormatSpec::parse(spec: {spec})");
+        let chars: Vec<char> = spec.chars().collect();
+        let mut result = Self {
+            fill: ' ',
+            align: None,
+            sign: false,
+            zero: false,
+            width: 0,
+            precision: None,
+        };
+        let mut i = 0;
+
+        if let Some(align) = chars.get(1).and_then(|c| Align::from_char(*c)) {
+            result.fill = chars[0];
+            result.align = Some(align);
+            i = 2;
+        } else if let Some(align) = chars.first().and_then(|c| Align::from_char(*c)) {
+            result.align = Some(align);
+            i = 1;
+        }
+
+        if chars.get(i) == Some(&'+') {
+            result.sign = true;
+            i += 1;
+        }
+
+        if chars.get(i) == Some(&'0') {
+            result.zero = true;
+            i += 1;
+        }
+
+        let (width, next) = Self::parse_number(&chars, i, "width", spec)?;
+        result.width = width.unwrap_or(0);
+        i = next;
+
+        if chars.get(i) == Some(&'.') {
+            let (precision, next) = Self::parse_number(&chars, i + 1, "precision", spec)?;
+            let Some(precision) = precision else {
+                return Err(format!("Missing precision in format spec '{spec}'."));
+            };
+            result.precision = Some(precision);
+            i = next;
+        }
+
+        if i != chars.len() {
+            return Err(format!("Invalid format spec '{spec}'."));
+        }
+
+        Ok(result)
+    }
+
+    /// Reads the digits at `start`, returning `None` when there are none,
+    /// and the index after them.
+    fn parse_number(
+        chars: &[char],
+        start: usize,
+        what: &str,
+        spec: &str,
+    ) -> Result<(Option<usize>, usize), String> {
+        let mut end = start;
+        while end < chars.len() && chars[end].is_ascii_digit() {
+            end += 1;
+        }
+        if end == start {
+            return Ok((None, end));
+        }
+
+        match chars[start..end].iter().collect::<String>().parse() {
+            Ok(number) if number <= MAX_SPEC_NUMBER => Ok((Some(number), end)),
+            _ => Err(format!(
+                "Format {what} in '{spec}' is larger than {MAX_SPEC_NUMBER}."
+            )),
+        }
+    }
+
+    fn write(&self, output: &mut String, arg: &Arg) {
+        trace!("format::FormatSpec::write()");
+        let (body, default_align) = match arg {
+            Arg::Number(value) => {
+                let mut body = match self.precision {
+                    Some(precision) => format!("{value:.precision$}"),
+                    None => value.to_string(),
+                };
+                if self.sign && value.is_sign_positive() && !value.is_nan() {
+                    body.insert(0, '+');
+                }
+                (body, Align::Right)
+            }
+            Arg::Text(text) => {
+                let body = match self.precision {
+                    Some(precision) => text.chars().take(precision).collect(),
+                    None => text.clone(),
+                };
+                (body, Align::Left)
+            }
+        };
+
+        let len = body.chars().count();
+        if len >= self.width {
+            output.push_str(&body);
+            return;
+        }
+        let padding = self.width - len;
+
+        // Zero padding goes between the sign and the digits, like `{:+05}`.
+        // As in Rust, it overrides any fill and alignment for numbers.
+        if self.zero && matches!(arg, Arg::Number(_)) {
+            let sign_len = if body.starts_with(['+', '-']) { 1 } else { 0 };
+            output.push_str(&body[..sign_len]);
+            output.extend(std::iter::repeat_n('0', padding));
+            output.push_str(&body[sign_len..]);
+            return;
+        }
+
+        let (before, after) = match self.align.unwrap_or(default_align) {
+            Align::Left => (0, padding),
+            Align::Center => (padding / 2, padding - padding / 2),
+            Align::Right => (padding, 0),
+        };
+        output.extend(std::iter::repeat_n(self.fill, before));
+        output.push_str(&body);
+        output.extend(std::iter::repeat_n(self.fill, after));
+    }
+}
+
+/// Renders `template` the way `format!` would, with `{}` taking the next
+/// argument and `{n}` a positional one. `{{` and `}}` are literal braces.
+pub fn format(template: &str, args: &[Arg]) -> Result<String, String> {
+    trace!("format::format(template: {template})");
+    let mut output = String::new();
+    let mut chars = template.chars().peekable();
+    let mut next_arg = 0;
+
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                output.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                output.push('}');
+            }
+            '{' => {
+                let placeholder = read_placeholder(&mut chars)?;
+                let (index, spec) = placeholder.split_once(':').unwrap_or((&placeholder, ""));
+
+                let index = if index.is_empty() {
+                    next_arg += 1;
+                    next_arg - 1
+                } else {
+                    index.parse().map_err(|_| {
+                        format!("Invalid argument index '{index}' in format string.")
+                    })?
+                };
+
+                let Some(arg) = args.get(index) else {
+                    return Err(format!(
+                        "Format string needs argument {index}, but got {} arguments.",
+                        args.len()
+                    ));
+                };
+
+                FormatSpec::parse(spec)?.write(&mut output, arg);
+            }
+            '}' => return Err(String::from("Unmatched '}' in format string.")),
+            c => output.push(c),
+        }
+    }
+
+    Ok(output)
+}
+
+fn read_placeholder(chars: &mut Peekable<Chars>) -> Result<String, String> {
+    let mut placeholder = String::new();
+
+    loop {
+        match chars.next() {
+            Some('}') => return Ok(placeholder),
+            Some(c) => placeholder.push(c),
+            None => return Err(String::from("Unclosed '{' in format string.")),
+        }
+    }
+}
@@ -1,19 +1,30 @@
 use std::rc::Rc;
 
-use crate::{value::Value, vm::Chunk};
+use crate::{heap::Handle, register::RegisterCode, value::Value, vm::Chunk};
 
 #[derive(Debug, PartialEq)]
 pub enum FunctionType {
     Function,
+    Initializer,
+    Method,
+    Getter,
+    Setter,
     Script,
 }
 
-#[derive(Debug, Clone)]
+/// Compiled code of a function. It is never modified once the parser is
+/// done with it, so closures, call frames and constants share one copy.
+#[derive(Debug)]
 pub struct Function {
     pub arity: usize,
     pub chunk: Chunk,
     pub name: Option<String>,
     pub upvalue_count: usize,
+    /// Calls return a promise, and the body may suspend at `await`.
+    pub is_async: bool,
+    /// The body lowered for `--engine register`, if the lowering handles
+    /// every instruction in it.
+    pub register_code: Option<RegisterCode>,
 }
 
 impl Function {
@@ -23,6 +34,8 @@ impl Function {
             chunk,
             name,
             upvalue_count,
+            is_async: false,
+            register_code: None,
         }
     }
 }
@@ -33,36 +46,44 @@ pub enum UpvalueLoc {
     Closed,
 }
 
+/// A captured variable, shared by every closure that captured it. While the
+/// variable is still on the stack the cell only records its slot; closing it
+/// moves the value into the cell.
 #[derive(Debug, Clone)]
 pub struct Upvalue {
     pub loc: UpvalueLoc,
-    pub value: Rc<Value>,
+    pub value: Value,
 }
 
 impl Upvalue {
     pub fn new_open(index: usize) -> Self {
         Self {
             loc: UpvalueLoc::Open(index),
-            value: Rc::new(Value::Nil),
+            value: Value::Nil,
         }
     }
 
-    pub fn new_closed(value: Rc<Value>) -> Self {
-        Self {
-            loc: UpvalueLoc::Closed,
-            value,
-        }
+    pub fn close(&mut self, value: Value) {
+        self.loc = UpvalueLoc::Closed;
+        self.value = value;
+    }
+
+    /// Points the cell back at a stack slot and hands out the value it held,
+    /// which belongs in that slot.
+    pub fn reopen(&mut self, index: usize) -> Value {
+        self.loc = UpvalueLoc::Open(index);
+        std::mem::replace(&mut self.value, Value::Nil)
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct Closure {
-    pub function: Function,
-    pub upvalues: Vec<usize>,
+    pub function: Rc<Function>,
+    pub upvalues: Vec<Handle<Upvalue>>,
 }
 
 impl Closure {
-    pub fn new(function: Function, upvalues: Vec<usize>) -> Self {
+    pub fn new(function: Rc<Function>, upvalues: Vec<Handle<Upvalue>>) -> Self {
         Self { function, upvalues }
     }
 }
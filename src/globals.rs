@@ -0,0 +1,79 @@
+use crate::interner::{InternedMap, InternedString};
+use crate::value::Value;
+
+/// Global variables, stored by slot. The compiler gives each global name a
+/// slot the first time it sees the name, so `OP_GET_GLOBAL` indexes a vector
+/// instead of looking the name up. The table lives in the VM and is shared
+/// by every compile, so a name keeps its slot across REPL inputs.
+#[derive(Debug)]
+pub struct Globals {
+    slots: InternedMap<usize>,
+    names: Vec<InternedString>,
+    /// `Value::Undefined` until the global's declaration has run.
+    values: Vec<Value>,
+}
+
+impl Globals {
+    pub fn new() -> Self {
+        trace!("globals::Globals::new()");
+        Self {
+            slots: InternedMap::default(),
+            names: vec![],
+            values: vec![],
+        }
+    }
+
+    /// The slot of `name`, handing out the next one if it has none yet.
+    pub fn slot(&mut self, name: InternedString) -> usize {
+        trace!("globals::Globals::slot(name: {name})");
+        if let Some(slot) = self.slots.get(&name) {
+            return *slot;
+        }
+
+        let slot = self.values.len();
+        self.slots.insert(name.clone(), slot);
+        self.names.push(name);
+        self.values.push(Value::Undefined);
+        slot
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn name(&self, slot: usize) -> &InternedString {
+        &self.names[slot]
+    }
+
+    /// The global's value, or `None` while it is undefined.
+    pub fn get(&self, slot: usize) -> Option<&Value> {
+        match &self.values[slot] {
+            Value::Undefined => None,
+            value => Some(value),
+        }
+    }
+
+    /// Mutable access to a defined global, so assignment can't create one.
+    pub fn get_mut(&mut self, slot: usize) -> Option<&mut Value> {
+        match &mut self.values[slot] {
+            Value::Undefined => None,
+            value => Some(value),
+        }
+    }
+
+    pub fn define(&mut self, slot: usize, value: Value) {
+        trace!("globals::Globals::define(slot: {slot}, value: {value})");
+        self.values[slot] = value;
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &Value> {
+        self.values.iter()
+    }
+
+    /// Undefines every global. Slots stay assigned, since compiled code
+    /// may still refer to them.
+    pub fn clear(&mut self) {
+        trace!("globals::Globals::clear()");
+        self.values.fill(Value::Undefined);
+    }
+}
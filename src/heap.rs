@@ -0,0 +1,383 @@
+use std::borrow::{Borrow, BorrowMut};
+use std::{fmt, marker::PhantomData, mem};
+
+use crate::class::{BoundMethod, Class, Instance, Trait};
+use crate::function::{Closure, Upvalue};
+use crate::task::{Promise, PromiseState, Task};
+use crate::value::Value;
+
+/// Bytes allocated before the first collection.
+const INITIAL_THRESHOLD: usize = 1024 * 1024;
+/// The next collection runs once the heap has grown this many times past
+/// what survived the last one.
+const GROWTH_FACTOR: usize = 2;
+
+/// Index of an object owned by a `Heap`. Handles are plain numbers, so values
+/// can be copied freely; the collector decides when the object goes away.
+pub struct Handle<T> {
+    index: usize,
+    marker: PhantomData<T>,
+}
+
+impl<T> Handle<T> {
+    fn new(index: usize) -> Self {
+        Self {
+            index,
+            marker: PhantomData,
+        }
+    }
+
+    /// Rebuilds a handle from `index`, which must have come from a handle to
+    /// the same type.
+    #[cfg(feature = "nan_boxing")]
+    pub fn from_index(index: usize) -> Self {
+        Self::new(index)
+    }
+
+    #[cfg(feature = "nan_boxing")]
+    pub fn index(self) -> usize {
+        self.index
+    }
+}
+
+impl<T> Clone for Handle<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Handle<T> {}
+
+impl<T> PartialEq for Handle<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.index == other.index
+    }
+}
+
+impl<T> fmt::Debug for Handle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{}", self.index)
+    }
+}
+
+#[derive(Debug)]
+pub enum Object {
+    Closure(Closure),
+    // Boxed because its method tables would otherwise make every object as
+    // large as a class.
+    Class(Box<Class>),
+    Instance(Instance),
+    BoundMethod(BoundMethod),
+    Trait(Trait),
+    List(Vec<Value>),
+    Promise(Promise),
+    Upvalue(Upvalue),
+}
+
+impl Object {
+    /// Rough footprint used to decide when to collect. Tables and lists
+    /// count their entries, so growing objects push the heap towards the
+    /// next collection too.
+    fn size(&self) -> usize {
+        let entries = match self {
+            Self::Closure(closure) => closure.upvalues.len(),
+            Self::Class(class) => {
+                class.methods.len()
+                    + class.getters.len()
+                    + class.setters.len()
+                    + class.static_methods.len()
+                    + class.static_fields.len()
+            }
+            Self::Instance(instance) => instance.fields.len(),
+            Self::BoundMethod(_) => 0,
+            Self::Trait(trait_def) => trait_def.methods.len(),
+            Self::List(items) => items.len(),
+            Self::Promise(_) => 0,
+            Self::Upvalue(_) => 0,
+        };
+
+        mem::size_of::<Self>() + entries * mem::size_of::<Value>()
+    }
+}
+
+/// Types that can live on the heap, and how to find them in an `Object`.
+pub trait HeapObject: Sized {
+    fn into_object(self) -> Object;
+    fn from_object(object: &Object) -> Option<&Self>;
+    fn from_object_mut(object: &mut Object) -> Option<&mut Self>;
+}
+
+macro_rules! heap_object {
+    ($variant:ident, $type:ty) => {
+        impl HeapObject for $type {
+            fn into_object(self) -> Object {
+                Object::$variant(self.into())
+            }
+
+            fn from_object(object: &Object) -> Option<&Self> {
+                match object {
+                    Object::$variant(value) => Some(value.borrow()),
+                    _ => None,
+                }
+            }
+
+            fn from_object_mut(object: &mut Object) -> Option<&mut Self> {
+                match object {
+                    Object::$variant(value) => Some(value.borrow_mut()),
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+heap_object!(Closure, Closure);
+heap_object!(Class, Class);
+heap_object!(Instance, Instance);
+heap_object!(BoundMethod, BoundMethod);
+heap_object!(Trait, Trait);
+heap_object!(List, Vec<Value>);
+heap_object!(Promise, Promise);
+heap_object!(Upvalue, Upvalue);
+
+/// Records reachable objects during the mark phase. Marked objects are
+/// gray until `Heap::trace` has marked everything they reference, and
+/// black afterwards; whatever is still white when the gray list runs dry
+/// is garbage.
+pub struct Marker<'a> {
+    marks: &'a mut [bool],
+    gray: &'a mut Vec<usize>,
+}
+
+impl Marker<'_> {
+    pub fn handle<T>(&mut self, handle: Handle<T>) {
+        if !self.marks[handle.index] {
+            self.marks[handle.index] = true;
+            self.gray.push(handle.index);
+        }
+    }
+
+    pub fn value(&mut self, value: &Value) {
+        match value {
+            Value::Closure { value } => self.handle(*value),
+            Value::Class { value } => self.handle(*value),
+            Value::Instance { value } => self.handle(*value),
+            Value::BoundMethod { value } => self.handle(*value),
+            Value::Trait { value } => self.handle(*value),
+            Value::List { value } => self.handle(*value),
+            Value::Promise { value } => self.handle(*value),
+            Value::Upvalue { value } => self.handle(*value),
+            Value::Variant { value } => {
+                for field in value.payload.iter() {
+                    self.value(field);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    pub fn task(&mut self, task: &Task) {
+        task.frame.trace(self);
+        for value in task.stack.iter() {
+            self.value(value);
+        }
+        for (_, upvalue) in task.upvalues.iter() {
+            self.handle(*upvalue);
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Heap {
+    objects: Vec<Option<Object>>,
+    marks: Vec<bool>,
+    gray: Vec<usize>,
+    free: Vec<usize>,
+    bytes_allocated: usize,
+    next_gc: usize,
+    /// Set by every allocation and cleared by every collection, so the
+    /// stress mode only collects when there is something new to find.
+    allocated_since_gc: bool,
+}
+
+impl Heap {
+    pub fn new() -> Self {
+        trace!("heap::Heap::new()");
+        Self {
+            objects: vec![],
+            marks: vec![],
+            gray: vec![],
+            free: vec![],
+            bytes_allocated: 0,
+            next_gc: INITIAL_THRESHOLD,
+            allocated_since_gc: false,
+        }
+    }
+
+    pub fn alloc<T: HeapObject>(&mut self, value: T) -> Handle<T> {
+        trace!("heap::Heap::alloc()");
+        let object = value.into_object();
+        self.bytes_allocated += object.size();
+        self.allocated_since_gc = true;
+
+        match self.free.pop() {
+            Some(index) => {
+                self.objects[index] = Some(object);
+                Handle::new(index)
+            }
+            None => {
+                self.objects.push(Some(object));
+                self.marks.push(false);
+                Handle::new(self.objects.len() - 1)
+            }
+        }
+    }
+
+    pub fn get<T: HeapObject>(&self, handle: Handle<T>) -> &T {
+        self.objects[handle.index]
+            .as_ref()
+            .and_then(T::from_object)
+            .expect("handle must point to a live object of its type")
+    }
+
+    pub fn get_mut<T: HeapObject>(&mut self, handle: Handle<T>) -> &mut T {
+        self.objects[handle.index]
+            .as_mut()
+            .and_then(T::from_object_mut)
+            .expect("handle must point to a live object of its type")
+    }
+
+    pub fn should_collect(&self, stress: bool) -> bool {
+        if stress {
+            self.allocated_since_gc
+        } else {
+            self.bytes_allocated > self.next_gc
+        }
+    }
+
+    /// Starts a collection. The caller marks its roots through the returned
+    /// `Marker` and then calls `sweep`.
+    pub fn marker(&mut self) -> Marker<'_> {
+        trace!("heap::Heap::marker()");
+        Marker {
+            marks: &mut self.marks,
+            gray: &mut self.gray,
+        }
+    }
+
+    /// Finishes marking from the gray objects and frees every object that
+    /// wasn't reached, returning how many were freed.
+    pub fn sweep(&mut self) -> usize {
+        trace!("heap::Heap::sweep()");
+        self.trace();
+
+        let mut freed = 0;
+        self.bytes_allocated = 0;
+        for (index, slot) in self.objects.iter_mut().enumerate() {
+            let Some(object) = slot else {
+                continue;
+            };
+
+            if self.marks[index] {
+                self.marks[index] = false;
+                self.bytes_allocated += object.size();
+            } else {
+                *slot = None;
+                self.free.push(index);
+                freed += 1;
+            }
+        }
+
+        self.next_gc = (self.bytes_allocated * GROWTH_FACTOR).max(INITIAL_THRESHOLD);
+        self.allocated_since_gc = false;
+
+        freed
+    }
+
+    pub fn len(&self) -> usize {
+        self.objects.len() - self.free.len()
+    }
+
+    pub fn clear(&mut self) {
+        trace!("heap::Heap::clear()");
+        self.objects.clear();
+        self.marks.clear();
+        self.free.clear();
+        self.bytes_allocated = 0;
+    }
+
+    fn trace(&mut self) {
+        let Self {
+            objects,
+            marks,
+            gray,
+            ..
+        } = self;
+
+        while let Some(index) = gray.pop() {
+            let Some(object) = &objects[index] else {
+                continue;
+            };
+            let mut marker = Marker {
+                marks: &mut *marks,
+                gray: &mut *gray,
+            };
+
+            match object {
+                Object::Closure(closure) => {
+                    for upvalue in closure.upvalues.iter() {
+                        marker.handle(*upvalue);
+                    }
+                }
+                Object::Class(class) => {
+                    let methods = class
+                        .methods
+                        .values()
+                        .chain(class.getters.values())
+                        .chain(class.setters.values())
+                        .chain(class.static_methods.values());
+                    for method in methods {
+                        marker.handle(*method);
+                    }
+                    for value in class.static_fields.values() {
+                        marker.value(value);
+                    }
+                    for trait_def in class.traits.iter() {
+                        marker.handle(*trait_def);
+                    }
+                }
+                Object::Instance(instance) => {
+                    marker.handle(instance.class);
+                    for value in instance.fields.values() {
+                        marker.value(value);
+                    }
+                }
+                Object::BoundMethod(bound) => {
+                    marker.value(&bound.receiver);
+                    marker.handle(bound.method);
+                }
+                Object::Trait(trait_def) => {
+                    for method in trait_def.methods.values() {
+                        marker.handle(*method);
+                    }
+                }
+                Object::List(items) => {
+                    for item in items.iter() {
+                        marker.value(item);
+                    }
+                }
+                Object::Promise(promise) => match &promise.state {
+                    PromiseState::Pending { waiters } => {
+                        for task in waiters.iter() {
+                            marker.task(task);
+                        }
+                    }
+                    PromiseState::Resolved { value } => marker.value(value),
+                },
+                // An open upvalue's variable is on the stack, which is a
+                // root already.
+                Object::Upvalue(upvalue) => marker.value(&upvalue.value),
+            }
+        }
+    }
+}
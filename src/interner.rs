@@ -0,0 +1,168 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    hash::{BuildHasherDefault, DefaultHasher, Hash, Hasher},
+    ops::Deref,
+    rc::Rc,
+};
+
+struct Entry {
+    hash: u64,
+    text: Box<str>,
+}
+
+/// A string owned by an `Interner`. There is only ever one live
+/// `InternedString` per text, so comparing two of them compares pointers,
+/// and hashing one reuses the hash computed when it was interned.
+#[derive(Clone)]
+pub struct InternedString(Rc<Entry>);
+
+impl InternedString {
+    pub fn as_str(&self) -> &str {
+        &self.0.text
+    }
+
+    /// Gives up the string's reference count as a raw pointer.
+    #[cfg(feature = "nan_boxing")]
+    pub fn into_raw(self) -> *const () {
+        Rc::into_raw(self.0).cast()
+    }
+
+    /// Takes back a reference count given up by `into_raw`.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must come from `into_raw`, and each pointer may only be taken
+    /// back once.
+    #[cfg(feature = "nan_boxing")]
+    pub unsafe fn from_raw(ptr: *const ()) -> Self {
+        Self(unsafe { Rc::from_raw(ptr.cast()) })
+    }
+}
+
+impl Deref for InternedString {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl PartialEq for InternedString {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for InternedString {}
+
+impl Hash for InternedString {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_u64(self.0.hash);
+    }
+}
+
+impl fmt::Display for InternedString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl fmt::Debug for InternedString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.as_str())
+    }
+}
+
+/// Hasher for maps keyed by `InternedString`, which passes the stored hash
+/// through instead of hashing it again.
+#[derive(Default)]
+pub struct PreHashed(u64);
+
+impl Hasher for PreHashed {
+    fn write(&mut self, _bytes: &[u8]) {
+        unreachable!("PreHashed only hashes interned strings");
+    }
+
+    fn write_u64(&mut self, hash: u64) {
+        self.0 = hash;
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+pub type InternedMap<V> = HashMap<InternedString, V, BuildHasherDefault<PreHashed>>;
+
+/// Strings interned before the first sweep.
+const INITIAL_SWEEP_THRESHOLD: usize = 1024;
+
+/// The set of live strings, bucketed by hash.
+#[derive(Debug)]
+pub struct Interner {
+    strings: HashMap<u64, Vec<InternedString>, BuildHasherDefault<PreHashed>>,
+    len: usize,
+    next_sweep: usize,
+}
+
+impl Interner {
+    pub fn new() -> Self {
+        trace!("interner::Interner::new()");
+        Self {
+            strings: HashMap::default(),
+            len: 0,
+            next_sweep: INITIAL_SWEEP_THRESHOLD,
+        }
+    }
+
+    pub fn intern(&mut self, text: &str) -> InternedString {
+        trace!("interner::Interner::intern(text: {text})");
+        let mut hasher = DefaultHasher::new();
+        text.hash(&mut hasher);
+        let hash = hasher.finish();
+
+        let bucket = self.strings.entry(hash).or_default();
+        if let Some(string) = bucket.iter().find(|string| string.as_str() == text) {
+            return string.clone();
+        }
+
+        let string = InternedString(Rc::new(Entry {
+            hash,
+            text: Box::from(text),
+        }));
+        bucket.push(string.clone());
+        self.len += 1;
+
+        // Sweeping needs no roots, so it can happen whenever the table has
+        // doubled rather than waiting for the collector.
+        if self.len > self.next_sweep {
+            self.sweep();
+            self.next_sweep = (self.len * 2).max(INITIAL_SWEEP_THRESHOLD);
+        }
+
+        string
+    }
+
+    /// Forgets strings nothing but the interner refers to any more. They
+    /// can't be compared against, so a later `intern` can safely make a new
+    /// one.
+    pub fn sweep(&mut self) {
+        trace!("interner::Interner::sweep()");
+        self.strings.retain(|_, bucket| {
+            bucket.retain(|string| Rc::strong_count(&string.0) > 1);
+            !bucket.is_empty()
+        });
+        self.len = self.strings.values().map(Vec::len).sum();
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+}
+
+impl fmt::Debug for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.text)
+    }
+}
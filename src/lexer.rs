@@ -1,7 +1,7 @@
 use std::iter::Peekable;
 use std::str::Chars;
 
-use log::trace;
+use unicode_ident::{is_xid_continue, is_xid_start};
 
 use crate::{
     error::LexerError,
@@ -76,9 +76,19 @@ impl<'a> Lexer<'a> {
             ')' => Ok(self.create_token(TokenType::RightParen)),
             '{' => Ok(self.create_token(TokenType::LeftBrace)),
             '}' => Ok(self.create_token(TokenType::RightBrace)),
+            '[' => Ok(self.create_token(TokenType::LeftBracket)),
+            ']' => Ok(self.create_token(TokenType::RightBracket)),
             ';' => Ok(self.create_token(TokenType::Semi)),
             ',' => Ok(self.create_token(TokenType::Comma)),
-            '.' => Ok(self.create_token(TokenType::Dot)),
+            '.' => {
+                if self.raw_source[self.current..].starts_with("..") {
+                    self.advance();
+                    self.advance();
+                    Ok(self.create_token(TokenType::DotDotDot))
+                } else {
+                    Ok(self.create_token(TokenType::Dot))
+                }
+            }
             '+' => Ok(self.create_token(TokenType::Plus)),
             '-' => Ok(self.create_token(TokenType::Minus)),
             '*' => Ok(self.create_token(TokenType::Star)),
@@ -110,6 +120,18 @@ impl<'a> Lexer<'a> {
                     Ok(self.create_token(TokenType::Less))
                 }
             }
+            '?' => {
+                if self.match_char('.') {
+                    Ok(self.create_token(TokenType::QuestionDot))
+                } else if self.match_char('[') {
+                    Ok(self.create_token(TokenType::QuestionBracket))
+                } else {
+                    Err(LexerError::UnexpectedCharacter {
+                        line: self.line,
+                        col: self.col + 1,
+                    })
+                }
+            }
             '/' => {
                 if self.match_char('/') {
                     self.single_line_comment()
@@ -120,8 +142,12 @@ impl<'a> Lexer<'a> {
                 }
             }
             '"' | '\'' => self.string(c),
+            'r' if matches!(self.source.peek(), Some('"' | '\'')) => {
+                let quote = self.advance().unwrap();
+                self.string(quote)
+            }
             '0'..='9' => self.number(),
-            'a'..='z' | 'A'..='Z' | '_' => self.identifier(c),
+            c if c == '_' || is_xid_start(c) => self.identifier(c),
             _ => Err(LexerError::UnexpectedCharacter {
                 line: self.line,
                 col: self.col + 1,
@@ -144,9 +170,8 @@ impl<'a> Lexer<'a> {
     fn advance(&mut self) -> Option<char> {
         trace!("lexer::Lexer::advance()");
         if let Some(c) = self.source.next() {
-            let len = c.len_utf8();
-            self.current += len;
-            self.col += len;
+            self.current += c.len_utf8();
+            self.col += 1;
             Some(c)
         } else {
             None
@@ -159,9 +184,8 @@ impl<'a> Lexer<'a> {
             Some(c) => {
                 if c == expected {
                     _ = self.source.next();
-                    let len = c.len_utf8();
-                    self.current += len;
-                    self.col += len;
+                    self.current += c.len_utf8();
+                    self.col += 1;
                     true
                 } else {
                     false
@@ -184,7 +208,7 @@ impl<'a> Lexer<'a> {
                 }
                 _ => {
                     self.current += c.len_utf8();
-                    self.col += c.len_utf8();
+                    self.col += 1;
                     _ = self.source.next();
                 }
             }
@@ -193,8 +217,19 @@ impl<'a> Lexer<'a> {
 
     fn check_keyword(&mut self, start: usize, end: usize, snippet: &str) -> bool {
         trace!("lexer::Lexer::check_keyword(start: {start}, end: {end}, snippet: {snippet})");
-        if end <= self.raw_source.len() {
-            if &self.raw_source[start..end] == snippet {
+        let Some(candidate) = self.raw_source.get(start..end) else {
+            return false;
+        };
+
+        // A keyword must not be followed by further identifier characters,
+        // otherwise `order` would lex as `or` followed by `der`.
+        let is_boundary = match self.raw_source[end..].chars().next() {
+            Some(c) => !is_xid_continue(c),
+            None => true,
+        };
+
+        if is_boundary {
+            if candidate == snippet {
                 for _ in start..end {
                     _ = self.source.next();
                 }
@@ -221,7 +256,7 @@ impl<'a> Lexer<'a> {
             if next_c == '\n' {
                 break;
             } else {
-                self.current += 1;
+                self.current += next_c.len_utf8();
                 self.col += 1;
             }
         }
@@ -273,7 +308,7 @@ impl<'a> Lexer<'a> {
                 self.line += 1;
             } else {
                 self.current += next_c.len_utf8();
-                self.col += next_c.len_utf8();
+                self.col += 1;
             }
         }
 
@@ -285,10 +320,27 @@ impl<'a> Lexer<'a> {
         ))
     }
 
+    /// Lexes a string whose opening `c` has been consumed. Three quotes in a
+    /// row open a string that only ends at the next three. Indentation is
+    /// resolved by the parser.
     fn string(&mut self, c: char) -> Result<Token, LexerError> {
         trace!("lexer::Lexer::string(c: {c})");
+        let triple = String::from(c).repeat(3);
+        let is_triple = self.raw_source[self.current..].starts_with(&triple[..2]);
+        if is_triple {
+            self.advance();
+            self.advance();
+        }
+
         loop {
-            let Some(next_c) = self.source.peek().copied() else {
+            if is_triple && self.raw_source[self.current..].starts_with(&triple) {
+                for _ in 0..3 {
+                    self.advance();
+                }
+                break;
+            }
+
+            let Some(next_c) = self.advance() else {
                 let error = Err(LexerError::UnterminatedString {
                     line: self.line,
                     col: self.col + 2,
@@ -297,19 +349,13 @@ impl<'a> Lexer<'a> {
                 return error;
             };
 
-            _ = self.source.next();
-
-            if next_c == c {
-                self.current += 1;
-                self.col += 1;
-                break;
-            } else if next_c == '\n' {
-                self.current += next_c.len_utf8();
-                self.col = 0;
-                self.line += 1;
-            } else {
-                self.current += next_c.len_utf8();
-                self.col += next_c.len_utf8();
+            match next_c {
+                '\n' => {
+                    self.col = 0;
+                    self.line += 1;
+                }
+                _ if next_c == c && !is_triple => break,
+                _ => {}
             }
         }
         Ok(self.create_token(TokenType::String))
@@ -317,70 +363,143 @@ impl<'a> Lexer<'a> {
 
     fn number(&mut self) -> Result<Token, LexerError> {
         trace!("lexer::Lexer::number()");
-        let mut has_dot = false;
+        let radix = match (
+            &self.raw_source[self.start..self.current],
+            self.source.peek(),
+        ) {
+            ("0", Some('x' | 'X')) => 16,
+            ("0", Some('o' | 'O')) => 8,
+            ("0", Some('b' | 'B')) => 2,
+            _ => 10,
+        };
 
-        loop {
-            let Some(next_c) = self.source.peek().copied() else {
-                return Ok(self.create_token(TokenType::Number));
-            };
+        if radix != 10 {
+            self.advance();
+            if !self.digits(radix, false)? {
+                return Err(self.invalid_number("expected digits after the radix prefix"));
+            }
+        } else {
+            self.digits(10, true)?;
 
-            match next_c {
-                '.' => {
-                    if has_dot {
-                        let error = Err(LexerError::UnexpectedCharacter {
-                            line: self.line,
-                            col: self.col + 2,
-                        });
-                        self.next_whitespace();
-                        return error;
-                    }
+            let mut lookahead = self.raw_source[self.current..].chars();
+            if lookahead.next() == Some('.') && lookahead.next().is_some_and(|c| c.is_ascii_digit())
+            {
+                self.advance();
+                self.digits(10, false)?;
+            }
 
-                    has_dot = true;
-                    _ = self.source.next();
-                }
-                '0'..='9' => {
-                    self.current += 1;
-                    self.col += 1;
-                    _ = self.source.next();
+            if let Some('e' | 'E') = self.source.peek() {
+                self.advance();
+                if let Some('+' | '-') = self.source.peek() {
+                    self.advance();
                 }
-                'a'..='z' | 'A'..='Z' | '_' => {
-                    let error = Err(LexerError::UnexpectedCharacter {
-                        line: self.line,
-                        col: self.col + 2,
-                    });
-                    self.next_whitespace();
-                    return error;
+                if !self.digits(10, false)? {
+                    return Err(self.invalid_number("expected digits in the exponent"));
                 }
-                _ => {
-                    break;
+            }
+        }
+
+        match self.source.peek().copied() {
+            Some('.') if radix == 10 && self.raw_source[self.start..self.current].contains('.') => {
+                Err(self.invalid_number("unexpected second '.'"))
+            }
+            Some(c) if c.is_alphanumeric() || c == '_' => {
+                Err(self.invalid_number(format!("unexpected '{c}' in number").as_str()))
+            }
+            _ => Ok(self.create_token(TokenType::Number)),
+        }
+    }
+
+    /// Consumes a run of digits in `radix`, allowing single `_` separators
+    /// between them. Returns whether any digit was read.
+    fn digits(&mut self, radix: u32, started: bool) -> Result<bool, LexerError> {
+        trace!("lexer::Lexer::digits(radix: {radix}, started: {started})");
+        let mut last_was_digit = started;
+        let mut any = started;
+
+        while let Some(c) = self.source.peek().copied() {
+            if c.is_digit(radix) {
+                last_was_digit = true;
+                any = true;
+            } else if c == '_' {
+                if !last_was_digit {
+                    return Err(self.invalid_number("'_' must separate digits"));
                 }
+                last_was_digit = false;
+            } else if radix != 10 && c.is_ascii_alphanumeric() {
+                let message = format!("invalid digit '{c}' for a base {radix} literal");
+                return Err(self.invalid_number(message.as_str()));
+            } else {
+                break;
+            }
+            self.advance();
+        }
+
+        if any && !last_was_digit {
+            return Err(self.invalid_number("'_' must separate digits"));
+        }
+
+        Ok(any)
+    }
+
+    /// Builds an `InvalidNumber` error pointing at the next character, then
+    /// skips the rest of the literal so lexing resumes after it.
+    fn invalid_number(&mut self, reason: &str) -> LexerError {
+        trace!("lexer::Lexer::invalid_number(reason: {reason})");
+        let error = LexerError::InvalidNumber {
+            line: self.line,
+            col: self.col,
+            reason: String::from(reason),
+        };
+
+        while let Some(c) = self.source.peek().copied() {
+            if !(c.is_alphanumeric() || c == '_' || c == '.') {
+                break;
             }
+            self.advance();
         }
 
-        Ok(self.create_token(TokenType::Number))
+        error
     }
 
     fn identifier(&mut self, c: char) -> Result<Token, LexerError> {
         trace!("lexer::Lexer::identifier(c: {c})");
         match c {
             'a' => {
-                if self.check_keyword(self.start + 1, self.start + 3, "and") {
+                if self.check_keyword(self.start + 1, self.start + 3, "nd") {
                     return Ok(self.create_token(TokenType::And));
+                } else if self.check_keyword(self.start + 1, self.start + 6, "ssert") {
+                    return Ok(self.create_token(TokenType::Assert));
+                } else if self.check_keyword(self.start + 1, self.start + 5, "sync") {
+                    return Ok(self.create_token(TokenType::Async));
+                } else if self.check_keyword(self.start + 1, self.start + 5, "wait") {
+                    return Ok(self.create_token(TokenType::Await));
                 }
             }
             'c' => {
                 if self.check_keyword(self.start + 1, self.start + 5, "lass") {
                     return Ok(self.create_token(TokenType::Class));
+                } else if self.check_keyword(self.start + 1, self.start + 4, "ase") {
+                    return Ok(self.create_token(TokenType::Case));
+                }
+            }
+            'd' => {
+                if self.check_keyword(self.start + 1, self.start + 5, "efer") {
+                    return Ok(self.create_token(TokenType::Defer));
                 }
             }
             'e' => {
                 if self.check_keyword(self.start + 1, self.start + 4, "lse") {
                     return Ok(self.create_token(TokenType::Else));
+                } else if self.check_keyword(self.start + 1, self.start + 4, "num") {
+                    return Ok(self.create_token(TokenType::Enum));
                 }
             }
             'i' => {
                 if self.check_keyword(self.start + 1, self.start + 2, "f") {
                     return Ok(self.create_token(TokenType::If));
+                } else if self.check_keyword(self.start + 1, self.start + 2, "s") {
+                    return Ok(self.create_token(TokenType::Is));
                 }
             }
             'n' => {
@@ -416,6 +535,8 @@ impl<'a> Lexer<'a> {
             'w' => {
                 if self.check_keyword(self.start + 1, self.start + 5, "hile") {
                     return Ok(self.create_token(TokenType::While));
+                } else if self.check_keyword(self.start + 1, self.start + 4, "ith") {
+                    return Ok(self.create_token(TokenType::With));
                 }
             }
             'f' => {
@@ -464,6 +585,11 @@ impl<'a> Lexer<'a> {
                         self.current += 1;
                         self.col += 1;
                         return Ok(self.create_token(TokenType::True));
+                    } else if self.check_keyword(self.start + 2, self.start + 5, "ait") {
+                        _ = self.source.next();
+                        self.current += 1;
+                        self.col += 1;
+                        return Ok(self.create_token(TokenType::Trait));
                     }
                 }
             }
@@ -475,14 +601,10 @@ impl<'a> Lexer<'a> {
                 return Ok(self.create_token(TokenType::Identifier));
             };
 
-            match next_c {
-                '0'..='9' | 'a'..='z' | 'A'..='Z' | '_' => {
-                    self.current += 1;
-                    self.col += 1;
-                    _ = self.source.next();
-                }
-                _ => break,
+            if !is_xid_continue(next_c) {
+                break;
             }
+            self.advance();
         }
 
         Ok(self.create_token(TokenType::Identifier))
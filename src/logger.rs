@@ -1,5 +1,16 @@
 use log::{Metadata, Record};
 
+/// `log::trace!`, compiled in only with the `trace_logging` feature. Most
+/// functions trace their calls, several per instruction in the VM, and
+/// even a call the log level filters out costs a check.
+macro_rules! trace {
+    ($($arg:tt)*) => {
+        if cfg!(feature = "trace_logging") {
+            log::trace!($($arg)*)
+        }
+    };
+}
+
 pub struct Logger;
 
 impl log::Log for Logger {
@@ -1,14 +1,30 @@
 #![feature(gen_blocks)]
+// First, so the modules below can use its `trace!`.
+#[macro_use]
+mod logger;
+
 mod args;
+mod class;
 mod compile;
 mod debug;
+mod enums;
 mod error;
+mod format;
 mod function;
+mod globals;
+mod heap;
+mod interner;
 mod lexer;
-mod logger;
+#[cfg(feature = "nan_boxing")]
+mod nanbox;
+mod optimizer;
 mod parser;
+mod register;
+mod stack;
+mod task;
 mod token;
 mod value;
+mod verify;
 mod vm;
 
 use std::{
@@ -0,0 +1,240 @@
+use std::mem::{self, ManuallyDrop};
+use std::{fmt, ptr, rc::Rc};
+
+use crate::enums::{Enum, Variant};
+use crate::function::Function;
+use crate::heap::{Handle, Heap};
+use crate::interner::InternedString;
+use crate::value::Value;
+
+/// Set in every boxed value: the exponent and the top two mantissa bits.
+/// Numbers never have all of them set, as NaNs are stored as `f64::NAN`.
+const QNAN: u64 = 0x7ffc_0000_0000_0000;
+/// The low 48 bits, which hold the payload. Pointers fit, since the
+/// platforms we run on only use 48 bit addresses.
+const PAYLOAD: u64 = 0x0000_ffff_ffff_ffff;
+
+// What a boxed value holds, stored in the sign bit and bits 48 and 49.
+const SINGLETON: u64 = 0;
+const HANDLE: u64 = 1;
+const STRING: u64 = 2;
+const FUNCTION: u64 = 3;
+const ENUM: u64 = 4;
+const VARIANT: u64 = 5;
+/// Values rare enough on the stack to live behind an `Rc<Value>`.
+const BOXED: u64 = 6;
+
+// Payloads of `SINGLETON`.
+const NIL: u64 = 0;
+const FALSE: u64 = 1;
+const TRUE: u64 = 2;
+const UNDEFINED: u64 = 3;
+
+// The object type of a `HANDLE`, stored above its 32 bit heap index.
+const CLOSURE: u64 = 0;
+const UPVALUE: u64 = 1;
+const CLASS: u64 = 2;
+const INSTANCE: u64 = 3;
+const BOUND_METHOD: u64 = 4;
+const TRAIT: u64 = 5;
+const LIST: u64 = 6;
+const PROMISE: u64 = 7;
+
+/// A `Value` packed into eight bytes. Numbers are stored as themselves, and
+/// everything else in the payload of a quiet NaN: singletons and heap
+/// handles inline, and reference counted values as the pointer their `Rc`
+/// gave up, which the box releases when it drops.
+pub struct NanBox(u64);
+
+const _: () = assert!(mem::size_of::<NanBox>() == 8);
+
+impl NanBox {
+    pub fn is_falsy(&self) -> bool {
+        self.0 == tag(SINGLETON, NIL)
+            || self.0 == tag(SINGLETON, FALSE)
+            || self.0 == tag(SINGLETON, UNDEFINED)
+    }
+
+    /// Compares like `Value::eq`, without unpacking numbers.
+    pub fn eq(&self, other: &NanBox, heap: &Heap) -> bool {
+        match (self.kind(), other.kind()) {
+            (None, None) => f64::from_bits(self.0) == f64::from_bits(other.0),
+            _ => self.with_value(|a| other.with_value(|b| a.eq(b, heap))),
+        }
+    }
+
+    /// `None` for numbers.
+    fn kind(&self) -> Option<u64> {
+        if self.0 & QNAN != QNAN {
+            return None;
+        }
+        Some((self.0 >> 61) & 4 | (self.0 >> 48) & 3)
+    }
+
+    fn pointer<T>(&self) -> *const T {
+        ptr::with_exposed_provenance((self.0 & PAYLOAD) as usize)
+    }
+
+    /// Runs `f` on the unpacked value, leaving the reference counts as they
+    /// were.
+    fn with_value<R>(&self, f: impl FnOnce(&Value) -> R) -> R {
+        if self.kind() == Some(BOXED) {
+            let boxed = ManuallyDrop::new(unsafe { Rc::from_raw(self.pointer::<Value>()) });
+            return f(&boxed);
+        }
+
+        let value = ManuallyDrop::new(unsafe { self.unpack() });
+        f(&value)
+    }
+
+    /// Rebuilds the value, which takes over the reference count the box
+    /// holds.
+    ///
+    /// # Safety
+    ///
+    /// The count must not be released twice: either the box is forgotten
+    /// or the value is.
+    unsafe fn unpack(&self) -> Value {
+        let payload = self.0 & PAYLOAD;
+        match self.kind() {
+            None => Value::Number {
+                value: f64::from_bits(self.0),
+            },
+            Some(SINGLETON) => match payload {
+                NIL => Value::Nil,
+                FALSE => Value::Bool { value: false },
+                TRUE => Value::Bool { value: true },
+                _ => Value::Undefined,
+            },
+            Some(HANDLE) => {
+                let index = (payload & u32::MAX as u64) as usize;
+                match payload >> 32 {
+                    CLOSURE => Value::Closure {
+                        value: Handle::from_index(index),
+                    },
+                    UPVALUE => Value::Upvalue {
+                        value: Handle::from_index(index),
+                    },
+                    CLASS => Value::Class {
+                        value: Handle::from_index(index),
+                    },
+                    INSTANCE => Value::Instance {
+                        value: Handle::from_index(index),
+                    },
+                    BOUND_METHOD => Value::BoundMethod {
+                        value: Handle::from_index(index),
+                    },
+                    TRAIT => Value::Trait {
+                        value: Handle::from_index(index),
+                    },
+                    LIST => Value::List {
+                        value: Handle::from_index(index),
+                    },
+                    _ => Value::Promise {
+                        value: Handle::from_index(index),
+                    },
+                }
+            }
+            Some(STRING) => Value::String {
+                value: unsafe { InternedString::from_raw(self.pointer()) },
+            },
+            Some(FUNCTION) => Value::Function {
+                value: unsafe { Rc::from_raw(self.pointer::<Function>()) },
+            },
+            Some(ENUM) => Value::Enum {
+                value: unsafe { Rc::from_raw(self.pointer::<Enum>()) },
+            },
+            Some(VARIANT) => Value::Variant {
+                value: unsafe { Rc::from_raw(self.pointer::<Variant>()) },
+            },
+            Some(_) => Rc::unwrap_or_clone(unsafe { Rc::from_raw(self.pointer::<Value>()) }),
+        }
+    }
+}
+
+fn tag(kind: u64, payload: u64) -> u64 {
+    QNAN | (kind & 4) << 61 | (kind & 3) << 48 | payload
+}
+
+fn handle<T>(object_type: u64, handle: Handle<T>) -> u64 {
+    let index = handle.index();
+    assert!(index <= u32::MAX as usize, "heap index too large to box");
+    tag(HANDLE, object_type << 32 | index as u64)
+}
+
+fn pointer<T>(kind: u64, pointer: *const T) -> u64 {
+    let address = pointer.expose_provenance() as u64;
+    assert!(address & !PAYLOAD == 0, "pointer too wide to box");
+    tag(kind, address)
+}
+
+impl From<Value> for NanBox {
+    fn from(value: Value) -> Self {
+        let bits = match value {
+            Value::Number { value } if value.is_nan() => f64::NAN.to_bits(),
+            Value::Number { value } => value.to_bits(),
+            Value::Nil => tag(SINGLETON, NIL),
+            Value::Bool { value: false } => tag(SINGLETON, FALSE),
+            Value::Bool { value: true } => tag(SINGLETON, TRUE),
+            Value::Undefined => tag(SINGLETON, UNDEFINED),
+            Value::Closure { value } => handle(CLOSURE, value),
+            Value::Upvalue { value } => handle(UPVALUE, value),
+            Value::Class { value } => handle(CLASS, value),
+            Value::Instance { value } => handle(INSTANCE, value),
+            Value::BoundMethod { value } => handle(BOUND_METHOD, value),
+            Value::Trait { value } => handle(TRAIT, value),
+            Value::List { value } => handle(LIST, value),
+            Value::Promise { value } => handle(PROMISE, value),
+            Value::String { value } => pointer(STRING, value.into_raw()),
+            Value::Function { value } => pointer(FUNCTION, Rc::into_raw(value)),
+            Value::Enum { value } => pointer(ENUM, Rc::into_raw(value)),
+            Value::Variant { value } => pointer(VARIANT, Rc::into_raw(value)),
+            value => pointer(BOXED, Rc::into_raw(Rc::new(value))),
+        };
+
+        Self(bits)
+    }
+}
+
+impl From<NanBox> for Value {
+    fn from(value: NanBox) -> Self {
+        // The box's reference count moves into the value.
+        let value = ManuallyDrop::new(value);
+        unsafe { value.unpack() }
+    }
+}
+
+impl Clone for NanBox {
+    fn clone(&self) -> Self {
+        match self.kind() {
+            Some(BOXED) => unsafe { Rc::increment_strong_count(self.pointer::<Value>()) },
+            // A clone of the unpacked value holds the copy's count.
+            Some(STRING | FUNCTION | ENUM | VARIANT) => mem::forget(self.with_value(Value::clone)),
+            _ => {}
+        }
+
+        Self(self.0)
+    }
+}
+
+impl Drop for NanBox {
+    fn drop(&mut self) {
+        match self.kind() {
+            Some(BOXED) => unsafe { Rc::decrement_strong_count(self.pointer::<Value>()) },
+            Some(STRING | FUNCTION | ENUM | VARIANT) => drop(unsafe { self.unpack() }),
+            _ => {}
+        }
+    }
+}
+
+impl fmt::Display for NanBox {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.with_value(|value| write!(f, "{value}"))
+    }
+}
+
+impl fmt::Debug for NanBox {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.with_value(|value| write!(f, "{value:?}"))
+    }
+}
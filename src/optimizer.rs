@@ -0,0 +1,495 @@
+use crate::value::Value;
+use crate::vm::{Chunk, Loc, MAX_LONG_OPERAND, OpCode, decode_operand};
+
+/// An instruction decoded from a chunk, so the passes can drop and merge
+/// instructions without keeping byte offsets right. Jumps hold the index
+/// of the instruction they land on instead of an offset.
+#[derive(Debug)]
+struct Instruction {
+    op: OpCode,
+    /// The operand bytes as written, less a jump's offset.
+    operands: Vec<u8>,
+    target: usize,
+    /// Whether some jump lands here. Instructions control can arrive at
+    /// from elsewhere can't be merged into the one before them.
+    landing: bool,
+    loc: Loc,
+}
+
+impl Instruction {
+    fn new(op: OpCode, operands: Vec<u8>, loc: Loc) -> Self {
+        Self {
+            op,
+            operands,
+            target: 0,
+            landing: false,
+            loc,
+        }
+    }
+}
+
+/// A value an instruction pushes that the optimizer can compute with.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Literal {
+    Nil,
+    Bool(bool),
+    Number(f64),
+}
+
+impl Literal {
+    fn is_falsy(self) -> bool {
+        matches!(self, Self::Nil | Self::Bool(false))
+    }
+}
+
+/// Rewrites `chunk` in place: fuses a comparison and the `OP_NOT` after it
+/// into one instruction, folds operators applied to literals, drops values
+/// that are pushed only to be popped, and points jumps that land on jumps
+/// at where the chain ends. A chunk the passes can't make sense of, or
+/// whose jumps no longer fit after rewriting, is left as it was.
+pub fn optimize(chunk: &mut Chunk) {
+    trace!("optimizer::optimize()");
+    let Some(code) = decode(chunk) else {
+        return;
+    };
+
+    let mut constants = chunk.constants.clone();
+    let code = rewrite(code, fuse);
+    let code = rewrite(code, |out, instruction| {
+        fold(out, instruction, &mut constants)
+    });
+    let code = rewrite(code, drop_pop);
+    let code = rewrite(code, superinstruction);
+    let mut code = rewrite(code, pair_locals);
+    thread_jumps(&mut code);
+
+    if let Some(mut optimized) = encode(&code) {
+        optimized.constants = constants;
+        *chunk = optimized;
+    }
+}
+
+fn decode(chunk: &Chunk) -> Option<Vec<Instruction>> {
+    trace!("optimizer::decode()");
+    let mut starts = vec![];
+    let mut code = vec![];
+
+    let mut offset = 0;
+    while offset < chunk.len() {
+        let op = OpCode::from_u8(chunk.instructions[offset]);
+        if op == OpCode::Unknown {
+            return None;
+        }
+
+        let end = offset + chunk.instruction_len(offset);
+        let operands = chunk.instructions.get(offset + 1..end)?.to_vec();
+        let mut instruction = Instruction::new(op, operands, chunk.loc(offset));
+
+        // The offset is dropped from the operands; `target` replaces it.
+        if op.is_jump() {
+            instruction.target = op.jump_target(end, &instruction.operands)?;
+            instruction
+                .operands
+                .truncate(instruction.operands.len() - 2);
+        }
+
+        starts.push(offset);
+        code.push(instruction);
+        offset = end;
+    }
+    starts.push(offset);
+
+    // Jumps held byte offsets so far; every one has to land on the start
+    // of an instruction.
+    for instruction in code
+        .iter_mut()
+        .filter(|instruction| instruction.op.is_jump())
+    {
+        instruction.target = starts.binary_search(&instruction.target).ok()?;
+    }
+    mark_landings(&mut code);
+
+    Some(code)
+}
+
+fn mark_landings(code: &mut [Instruction]) {
+    for instruction in code.iter_mut() {
+        instruction.landing = false;
+    }
+
+    for index in 0..code.len() {
+        if code[index].op.is_jump() {
+            let target = code[index].target;
+            if let Some(instruction) = code.get_mut(target) {
+                instruction.landing = true;
+            }
+        }
+    }
+}
+
+/// Runs one pass: `step` gets the rewritten code so far and the next
+/// instruction, and pushes whatever that instruction becomes. It may also
+/// pop or change what it already pushed. Jumps are then pointed at where
+/// their targets went, so a jump to a dropped instruction lands on the one
+/// after it.
+fn rewrite(
+    code: Vec<Instruction>,
+    mut step: impl FnMut(&mut Vec<Instruction>, Instruction),
+) -> Vec<Instruction> {
+    let mut out = Vec::with_capacity(code.len());
+    let mut moved = Vec::with_capacity(code.len() + 1);
+
+    for instruction in code {
+        moved.push(out.len());
+        step(&mut out, instruction);
+    }
+    moved.push(out.len());
+
+    for instruction in out.iter_mut() {
+        if instruction.op.is_jump() {
+            instruction.target = moved[instruction.target];
+        }
+    }
+    mark_landings(&mut out);
+
+    out
+}
+
+/// `Equal, Not` becomes `NotEqual`, and likewise for the orderings.
+fn fuse(out: &mut Vec<Instruction>, instruction: Instruction) {
+    if instruction.op == OpCode::Not
+        && !instruction.landing
+        && let Some(last) = out.last_mut()
+    {
+        let fused = match last.op {
+            OpCode::Equal => Some(OpCode::NotEqual),
+            OpCode::Less => Some(OpCode::GreaterEqual),
+            OpCode::Greater => Some(OpCode::LessEqual),
+            _ => None,
+        };
+
+        if let Some(fused) = fused {
+            last.op = fused;
+            return;
+        }
+    }
+
+    out.push(instruction);
+}
+
+fn literal(instruction: &Instruction, constants: &[Value]) -> Option<Literal> {
+    match instruction.op {
+        OpCode::Nil => Some(Literal::Nil),
+        OpCode::True => Some(Literal::Bool(true)),
+        OpCode::False => Some(Literal::Bool(false)),
+        OpCode::Constant | OpCode::ConstantLong => {
+            let index = decode_operand(&instruction.operands);
+            match constants.get(index) {
+                Some(Value::Number { value }) => Some(Literal::Number(*value)),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// The instruction that pushes `literal`, adding a constant for numbers
+/// unless the table has the same one already.
+fn push_literal(literal: Literal, loc: Loc, constants: &mut Vec<Value>) -> Option<Instruction> {
+    let number = match literal {
+        Literal::Nil => return Some(Instruction::new(OpCode::Nil, vec![], loc)),
+        Literal::Bool(true) => return Some(Instruction::new(OpCode::True, vec![], loc)),
+        Literal::Bool(false) => return Some(Instruction::new(OpCode::False, vec![], loc)),
+        Literal::Number(number) => number,
+    };
+
+    let existing = constants.iter().position(|constant| match constant {
+        Value::Number { value } => value.to_bits() == number.to_bits(),
+        _ => false,
+    });
+    let index = match existing {
+        Some(index) => index,
+        None if constants.len() <= MAX_LONG_OPERAND => {
+            constants.push(Value::Number { value: number });
+            constants.len() - 1
+        }
+        None => return None,
+    };
+
+    Some(match u8::try_from(index) {
+        Ok(index) => Instruction::new(OpCode::Constant, vec![index], loc),
+        Err(_) => Instruction::new(
+            OpCode::ConstantLong,
+            (index as u32).to_be_bytes()[1..].to_vec(),
+            loc,
+        ),
+    })
+}
+
+fn fold_unary(op: OpCode, operand: Literal) -> Option<Literal> {
+    match (op, operand) {
+        (OpCode::Negate, Literal::Number(a)) => Some(Literal::Number(-a)),
+        (OpCode::Not, operand) => Some(Literal::Bool(operand.is_falsy())),
+        _ => None,
+    }
+}
+
+/// Only what the VM computes the same way for every pair of operands is
+/// folded; everything else is left to fail at runtime as it would.
+fn fold_binary(op: OpCode, a: Literal, b: Literal) -> Option<Literal> {
+    match (op, a, b) {
+        (OpCode::Equal, a, b) => Some(Literal::Bool(a == b)),
+        (OpCode::NotEqual, a, b) => Some(Literal::Bool(a != b)),
+        // The fused orderings negate the comparison, so NaN compares the
+        // way `!(a < b)` does.
+        (OpCode::GreaterEqual, a, b) => {
+            fold_binary(OpCode::Less, a, b).map(|less| Literal::Bool(less.is_falsy()))
+        }
+        (OpCode::LessEqual, a, b) => {
+            fold_binary(OpCode::Greater, a, b).map(|greater| Literal::Bool(greater.is_falsy()))
+        }
+        (_, Literal::Number(a), Literal::Number(b)) => match op {
+            OpCode::Add => Some(Literal::Number(a + b)),
+            OpCode::Subtract => Some(Literal::Number(a - b)),
+            OpCode::Multiply => Some(Literal::Number(a * b)),
+            OpCode::Divide => Some(Literal::Number(a / b)),
+            OpCode::Greater => Some(Literal::Bool(a > b)),
+            OpCode::Less => Some(Literal::Bool(a < b)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Replaces an operator applied to literals with the literal it computes.
+/// The operands have to be the last instructions pushed, and nothing may
+/// jump in between them, or the operator doesn't always see them.
+fn fold(out: &mut Vec<Instruction>, instruction: Instruction, constants: &mut Vec<Value>) {
+    if !instruction.landing
+        && let Some(folded) = fold_last(out, &instruction, constants)
+    {
+        out.push(folded);
+        return;
+    }
+
+    out.push(instruction);
+}
+
+fn fold_last(
+    out: &mut Vec<Instruction>,
+    instruction: &Instruction,
+    constants: &mut Vec<Value>,
+) -> Option<Instruction> {
+    let b = out.last()?;
+    let lb = literal(b, constants)?;
+
+    if let Some(result) = fold_unary(instruction.op, lb) {
+        let mut folded = push_literal(result, b.loc, constants)?;
+        folded.landing = b.landing;
+        out.pop();
+        return Some(folded);
+    }
+
+    let a = out.get(out.len().checked_sub(2)?)?;
+    if b.landing {
+        return None;
+    }
+    let result = fold_binary(instruction.op, literal(a, constants)?, lb)?;
+    let mut folded = push_literal(result, a.loc, constants)?;
+    folded.landing = a.landing;
+    out.truncate(out.len() - 2);
+    Some(folded)
+}
+
+/// Whether an instruction only pushes a value, so that pushing it and
+/// popping it right away does nothing.
+fn is_pure(op: OpCode) -> bool {
+    matches!(
+        op,
+        OpCode::Constant
+            | OpCode::ConstantLong
+            | OpCode::Nil
+            | OpCode::True
+            | OpCode::False
+            | OpCode::GetLocal
+            | OpCode::GetLocalLong
+            | OpCode::GetUpvalue
+            | OpCode::GetUpvalueLong
+    )
+}
+
+/// Drops a pure instruction and the `OP_POP` after it. Neither may be
+/// jumped to, or code arriving there would pop one value too few.
+fn drop_pop(out: &mut Vec<Instruction>, instruction: Instruction) {
+    if instruction.op == OpCode::Pop
+        && !instruction.landing
+        && out
+            .last()
+            .is_some_and(|last| is_pure(last.op) && !last.landing)
+    {
+        out.pop();
+        return;
+    }
+
+    out.push(instruction);
+}
+
+/// Replaces runs of local-variable instructions with a superinstruction
+/// as the run's last instruction arrives, so `i = i + 1;` first becomes
+/// `OP_ADD_LOCAL_CONST` and then, at the `OP_POP`, `OP_INCR_LOCAL`. Only
+/// the one byte operand forms are fused.
+fn superinstruction(out: &mut Vec<Instruction>, instruction: Instruction) {
+    if !instruction.landing
+        && let Some(fused) = fuse_run(out, &instruction)
+    {
+        out.push(fused);
+        return;
+    }
+
+    out.push(instruction);
+}
+
+fn fuse_run(out: &mut Vec<Instruction>, instruction: &Instruction) -> Option<Instruction> {
+    let (op, len) = match (out.as_slice(), instruction.op) {
+        ([.., a, b], OpCode::Add)
+            if a.op == OpCode::GetLocal && b.op == OpCode::Constant && !b.landing =>
+        {
+            (OpCode::AddLocalConst, 2)
+        }
+        ([.., a, b], OpCode::Pop)
+            if a.op == OpCode::AddLocalConst
+                && b.op == OpCode::SetLocal
+                && !b.landing
+                && a.operands[0] == b.operands[0] =>
+        {
+            (OpCode::IncrLocal, 2)
+        }
+        ([.., a, b, c, d], OpCode::Pop)
+            if a.op == OpCode::GetLocal
+                && b.op == OpCode::Constant
+                && c.op == OpCode::Less
+                && d.op == OpCode::JumpIfFalse
+                && [b, c, d].iter().all(|instruction| !instruction.landing) =>
+        {
+            (OpCode::JumpIfLessLocal, 4)
+        }
+        _ => return None,
+    };
+
+    let run = out.split_off(out.len() - len);
+    // The slot and constant come from the run's first two instructions,
+    // or from the `OP_ADD_LOCAL_CONST` that already holds both.
+    let operands = match op {
+        OpCode::IncrLocal => run[0].operands.clone(),
+        _ => [run[0].operands.as_slice(), run[1].operands.as_slice()].concat(),
+    };
+
+    let mut fused = Instruction::new(op, operands, run[0].loc);
+    fused.landing = run[0].landing;
+    fused.target = run[len - 1].target;
+    Some(fused)
+}
+
+/// `OP_GET_LOCAL` twice becomes `OP_GET_LOCAL2`. This runs after the other
+/// superinstructions, so it doesn't take a local one of them wants.
+fn pair_locals(out: &mut Vec<Instruction>, instruction: Instruction) {
+    if instruction.op == OpCode::GetLocal
+        && !instruction.landing
+        && let Some(last) = out.last_mut()
+        && last.op == OpCode::GetLocal
+    {
+        last.op = OpCode::GetLocal2;
+        last.operands.extend(instruction.operands);
+        return;
+    }
+
+    out.push(instruction);
+}
+
+/// Whether a jump `op` that lands on `next` would always go on to take it
+/// too: unconditional jumps are always taken, and the conditional ones
+/// peek at the value they test, so it is still the same when `next` does.
+fn continues(op: OpCode, next: OpCode) -> bool {
+    match next {
+        OpCode::Jump | OpCode::Loop => true,
+        OpCode::JumpIfFalse => matches!(
+            op,
+            OpCode::JumpIfFalse | OpCode::JumpIfNil | OpCode::JumpIfLessLocal
+        ),
+        OpCode::JumpIfNil => op == OpCode::JumpIfNil,
+        _ => false,
+    }
+}
+
+fn thread_jumps(code: &mut [Instruction]) {
+    trace!("optimizer::thread_jumps()");
+    for index in 0..code.len() {
+        let op = code[index].op;
+        if !op.is_jump() {
+            continue;
+        }
+
+        // A chain can loop back on itself, so it is followed at most as
+        // many times as there are instructions.
+        let mut target = code[index].target;
+        for _ in 0..code.len() {
+            match code.get(target) {
+                Some(next) if continues(op, next.op) && next.target != target => {
+                    target = next.target
+                }
+                _ => break,
+            }
+        }
+
+        match op {
+            OpCode::Jump | OpCode::Loop => {
+                code[index].op = if target > index {
+                    OpCode::Jump
+                } else {
+                    OpCode::Loop
+                };
+                code[index].target = target;
+            }
+            // Conditional jumps only go forward.
+            _ if target > index => code[index].target = target,
+            _ => {}
+        }
+    }
+}
+
+fn encode(code: &[Instruction]) -> Option<Chunk> {
+    trace!("optimizer::encode()");
+    let mut starts = Vec::with_capacity(code.len() + 1);
+    let mut offset = 0;
+    for instruction in code {
+        starts.push(offset);
+        offset += 1 + instruction.operands.len();
+        if instruction.op.is_jump() {
+            offset += 2;
+        }
+    }
+    starts.push(offset);
+
+    let mut chunk = Chunk::new();
+    for (index, instruction) in code.iter().enumerate() {
+        let Loc { line, col } = instruction.loc;
+        chunk.write(instruction.op as u8, line, col);
+        for byte in instruction.operands.iter() {
+            chunk.write(*byte, line, col);
+        }
+
+        if instruction.op.is_jump() {
+            let end = starts[index + 1];
+            let target = starts[instruction.target];
+            let jump = match instruction.op {
+                OpCode::Loop => end - target,
+                _ => target - end,
+            };
+            for byte in u16::try_from(jump).ok()?.to_be_bytes() {
+                chunk.write(byte, line, col);
+            }
+        }
+    }
+
+    Some(chunk)
+}
@@ -1,17 +1,20 @@
 use std::{iter::Peekable, rc::Rc};
 
-use log::trace;
-
 #[cfg(feature = "trace_execution")]
 use crate::debug::disassemble_chunk;
 
 use crate::{
+    args::Args,
+    enums::{Enum, EnumVariant},
     error::ParserError,
     function::{Function, FunctionType},
+    globals::Globals,
+    interner::Interner,
     lexer::LexerIterator,
+    optimizer::optimize,
     token::{Span, Token, TokenType},
     value::Value,
-    vm::{Chunk, OpCode},
+    vm::{Chunk, MAX_LONG_OPERAND, OpCode, UPVALUE_LOCAL, UPVALUE_LONG},
 };
 
 #[derive(Debug, PartialEq, PartialOrd)]
@@ -63,6 +66,13 @@ enum ParseFn {
     And,
     Or,
     Call,
+    Dot,
+    Index,
+    List,
+    OptionalDot,
+    OptionalIndex,
+    This,
+    Await,
 }
 
 fn get_prefix_rule(token_type: TokenType) -> ParseFn {
@@ -72,9 +82,12 @@ fn get_prefix_rule(token_type: TokenType) -> ParseFn {
         TokenType::RightParen => ParseFn::None,
         TokenType::LeftBrace => ParseFn::None,
         TokenType::RightBrace => ParseFn::None,
+        TokenType::LeftBracket => ParseFn::List,
+        TokenType::RightBracket => ParseFn::None,
         TokenType::Semi => ParseFn::None,
         TokenType::Comma => ParseFn::None,
         TokenType::Dot => ParseFn::None,
+        TokenType::DotDotDot => ParseFn::None,
         TokenType::Minus => ParseFn::Unary,
         TokenType::Plus => ParseFn::None,
         TokenType::Star => ParseFn::None,
@@ -87,25 +100,36 @@ fn get_prefix_rule(token_type: TokenType) -> ParseFn {
         TokenType::GreaterEqual => ParseFn::None,
         TokenType::Less => ParseFn::None,
         TokenType::LessEqual => ParseFn::None,
+        TokenType::QuestionDot => ParseFn::None,
+        TokenType::QuestionBracket => ParseFn::None,
         TokenType::Identifier => ParseFn::Variable,
         TokenType::String => ParseFn::String,
         TokenType::Number => ParseFn::Number,
         TokenType::And => ParseFn::None,
+        TokenType::Assert => ParseFn::None,
+        TokenType::Async => ParseFn::None,
+        TokenType::Await => ParseFn::Await,
+        TokenType::Case => ParseFn::None,
         TokenType::Class => ParseFn::None,
+        TokenType::Defer => ParseFn::None,
         TokenType::Else => ParseFn::None,
+        TokenType::Enum => ParseFn::None,
         TokenType::False => ParseFn::Literal,
         TokenType::For => ParseFn::None,
         TokenType::Fun => ParseFn::None,
         TokenType::If => ParseFn::None,
+        TokenType::Is => ParseFn::None,
         TokenType::Nil => ParseFn::Literal,
         TokenType::Or => ParseFn::None,
         TokenType::Print => ParseFn::None,
         TokenType::Return => ParseFn::None,
         TokenType::Super => ParseFn::None,
-        TokenType::This => ParseFn::None,
+        TokenType::This => ParseFn::This,
+        TokenType::Trait => ParseFn::None,
         TokenType::True => ParseFn::Literal,
         TokenType::Var => ParseFn::None,
         TokenType::While => ParseFn::None,
+        TokenType::With => ParseFn::None,
         TokenType::Eof => ParseFn::None,
         TokenType::Error => ParseFn::None,
         TokenType::Comment => ParseFn::None,
@@ -119,9 +143,12 @@ fn get_infix_rule(token_type: TokenType) -> ParseFn {
         TokenType::RightParen => ParseFn::None,
         TokenType::LeftBrace => ParseFn::None,
         TokenType::RightBrace => ParseFn::None,
+        TokenType::LeftBracket => ParseFn::Index,
+        TokenType::RightBracket => ParseFn::None,
         TokenType::Semi => ParseFn::None,
         TokenType::Comma => ParseFn::None,
-        TokenType::Dot => ParseFn::None,
+        TokenType::Dot => ParseFn::Dot,
+        TokenType::DotDotDot => ParseFn::None,
         TokenType::Minus => ParseFn::Binary,
         TokenType::Plus => ParseFn::Binary,
         TokenType::Star => ParseFn::Binary,
@@ -134,31 +161,74 @@ fn get_infix_rule(token_type: TokenType) -> ParseFn {
         TokenType::GreaterEqual => ParseFn::Binary,
         TokenType::Less => ParseFn::Binary,
         TokenType::LessEqual => ParseFn::Binary,
+        TokenType::QuestionDot => ParseFn::OptionalDot,
+        TokenType::QuestionBracket => ParseFn::OptionalIndex,
         TokenType::Identifier => ParseFn::None,
         TokenType::String => ParseFn::None,
         TokenType::Number => ParseFn::None,
         TokenType::And => ParseFn::And,
+        TokenType::Assert => ParseFn::None,
+        TokenType::Async => ParseFn::None,
+        TokenType::Await => ParseFn::None,
+        TokenType::Case => ParseFn::None,
         TokenType::Class => ParseFn::None,
+        TokenType::Defer => ParseFn::None,
         TokenType::Else => ParseFn::None,
+        TokenType::Enum => ParseFn::None,
         TokenType::False => ParseFn::None,
         TokenType::For => ParseFn::None,
         TokenType::Fun => ParseFn::None,
         TokenType::If => ParseFn::None,
+        TokenType::Is => ParseFn::Binary,
         TokenType::Nil => ParseFn::None,
         TokenType::Or => ParseFn::Or,
         TokenType::Print => ParseFn::None,
         TokenType::Return => ParseFn::None,
         TokenType::Super => ParseFn::None,
         TokenType::This => ParseFn::None,
+        TokenType::Trait => ParseFn::None,
         TokenType::True => ParseFn::None,
         TokenType::Var => ParseFn::None,
         TokenType::While => ParseFn::None,
+        TokenType::With => ParseFn::None,
         TokenType::Eof => ParseFn::None,
         TokenType::Error => ParseFn::None,
         TokenType::Comment => ParseFn::None,
     }
 }
 
+/// Strips the indentation shared by all non-blank lines of a triple-quoted
+/// string that spans several lines. A line break right after the opening quotes and a last line made
+/// only of whitespace before the closing quotes are dropped as well.
+fn dedent(text: &str) -> String {
+    // A literal on one line keeps its spaces, e.g. `"""  padded  """`.
+    if !text.contains('\n') {
+        return text.to_string();
+    }
+
+    let mut lines: Vec<&str> = text.split('\n').collect();
+
+    if lines.len() > 1 && lines[0].trim().is_empty() {
+        lines.remove(0);
+    }
+    if lines.len() > 1 && lines[lines.len() - 1].trim().is_empty() {
+        lines.pop();
+    }
+
+    let indent = lines
+        .iter()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line.len() - line.trim_start_matches([' ', '\t']).len())
+        .min()
+        .unwrap_or(0);
+
+    lines
+        .iter()
+        .map(|line| line.get(indent..).unwrap_or_default())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
 fn get_precedence_rule(token_type: TokenType) -> Precedence {
     trace!("parser::precedence_rule(token_type: {:?})", token_type);
     match token_type {
@@ -166,9 +236,12 @@ fn get_precedence_rule(token_type: TokenType) -> Precedence {
         TokenType::RightParen => Precedence::None,
         TokenType::LeftBrace => Precedence::None,
         TokenType::RightBrace => Precedence::None,
+        TokenType::LeftBracket => Precedence::Call,
+        TokenType::RightBracket => Precedence::None,
         TokenType::Semi => Precedence::None,
         TokenType::Comma => Precedence::None,
-        TokenType::Dot => Precedence::None,
+        TokenType::Dot => Precedence::Call,
+        TokenType::DotDotDot => Precedence::None,
         TokenType::Minus => Precedence::Term,
         TokenType::Plus => Precedence::Term,
         TokenType::Star => Precedence::Factor,
@@ -181,31 +254,49 @@ fn get_precedence_rule(token_type: TokenType) -> Precedence {
         TokenType::GreaterEqual => Precedence::Comparison,
         TokenType::Less => Precedence::Comparison,
         TokenType::LessEqual => Precedence::Comparison,
+        TokenType::QuestionDot => Precedence::Call,
+        TokenType::QuestionBracket => Precedence::Call,
         TokenType::Identifier => Precedence::None,
         TokenType::String => Precedence::None,
         TokenType::Number => Precedence::None,
         TokenType::And => Precedence::And,
+        TokenType::Assert => Precedence::None,
+        TokenType::Async => Precedence::None,
+        TokenType::Await => Precedence::None,
+        TokenType::Case => Precedence::None,
         TokenType::Class => Precedence::None,
+        TokenType::Defer => Precedence::None,
         TokenType::Else => Precedence::None,
+        TokenType::Enum => Precedence::None,
         TokenType::False => Precedence::None,
         TokenType::For => Precedence::None,
         TokenType::Fun => Precedence::None,
         TokenType::If => Precedence::None,
+        TokenType::Is => Precedence::Comparison,
         TokenType::Nil => Precedence::None,
         TokenType::Or => Precedence::Or,
         TokenType::Print => Precedence::None,
         TokenType::Return => Precedence::None,
         TokenType::Super => Precedence::None,
         TokenType::This => Precedence::None,
+        TokenType::Trait => Precedence::None,
         TokenType::True => Precedence::None,
         TokenType::Var => Precedence::None,
         TokenType::While => Precedence::None,
+        TokenType::With => Precedence::None,
         TokenType::Eof => Precedence::None,
         TokenType::Error => Precedence::None,
         TokenType::Comment => Precedence::None,
     }
 }
 
+/// Name of slot zero in methods and initializers. It never appears in the
+/// source at that position, so it can't borrow a real token span.
+const THIS_SPAN: Span = Span {
+    start: usize::MAX,
+    end: usize::MAX,
+};
+
 #[derive(Debug)]
 pub struct Local {
     pub name: Span,
@@ -234,6 +325,9 @@ pub struct CompileFrame {
     pub locals: Vec<Local>,
     pub upvalues: Vec<UpValue>,
     pub scope_depth: usize,
+    /// Offset of the most recently emitted `OP_CALL` or `OP_CALL_SPREAD`,
+    /// used to spot calls in tail position.
+    pub last_call: Option<usize>,
 }
 
 impl CompileFrame {
@@ -250,6 +344,7 @@ impl CompileFrame {
             locals,
             upvalues,
             scope_depth,
+            last_call: None,
         }
     }
 
@@ -281,10 +376,20 @@ pub struct Parser<'a> {
     panic: bool,
     frames: Vec<CompileFrame>,
     frame_index: usize,
+    no_asserts: bool,
+    optimize: bool,
+    interner: &'a mut Interner,
+    globals: &'a mut Globals,
 }
 
 impl<'a> Parser<'a> {
-    pub fn new(source: &'a str, tokens: Peekable<LexerIterator<'a>>) -> Self {
+    pub fn new(
+        source: &'a str,
+        tokens: Peekable<LexerIterator<'a>>,
+        args: &Args,
+        interner: &'a mut Interner,
+        globals: &'a mut Globals,
+    ) -> Self {
         trace!("parser::Parser::new(source, tokens)");
 
         let mut root_frame = CompileFrame::new(
@@ -308,6 +413,10 @@ impl<'a> Parser<'a> {
             panic: false,
             frames: vec![root_frame],
             frame_index: 0,
+            no_asserts: args.no_asserts,
+            optimize: args.opt_level > 0,
+            interner,
+            globals,
         }
     }
 
@@ -321,6 +430,12 @@ impl<'a> Parser<'a> {
 
         self.end_parse();
 
+        // The errors have been reported as they were found; code with
+        // errors in it must not run.
+        if self.had_error {
+            return Err(ParserError::HadErrors);
+        }
+
         let frame = self.frames.remove(0);
         let function = frame.function;
 
@@ -367,6 +482,9 @@ impl<'a> Parser<'a> {
                 | TokenType::If
                 | TokenType::While
                 | TokenType::Print
+                | TokenType::Defer
+                | TokenType::Assert
+                | TokenType::Async
                 | TokenType::Return => return,
                 _ => {}
             }
@@ -408,6 +526,7 @@ impl<'a> Parser<'a> {
                 }
                 Err(e) => {
                     e.report();
+                    self.had_error = true;
                     continue;
                 }
             }
@@ -458,6 +577,16 @@ impl<'a> Parser<'a> {
             }
         }
         self.emit_ops(OpCode::Nil, OpCode::Return);
+        self.optimize_frame();
+    }
+
+    /// Runs the optimizer over the innermost frame's finished chunk. Code
+    /// with errors in it is left alone, since it never runs.
+    fn optimize_frame(&mut self) {
+        trace!("parser::Parser::optimize_frame()");
+        if self.optimize && !self.had_error {
+            optimize(&mut self.current_frame_mut().function.chunk);
+        }
     }
 
     fn parse_precedence(&mut self, precedence: Precedence) {
@@ -494,6 +623,7 @@ impl<'a> Parser<'a> {
             ParseFn::Number => self.number(),
             ParseFn::Group => self.group(),
             ParseFn::Unary => self.unary(),
+            ParseFn::Await => self.await_expression(),
             ParseFn::Binary => self.binary(),
             ParseFn::Literal => self.literal(),
             ParseFn::String => self.string(),
@@ -501,6 +631,12 @@ impl<'a> Parser<'a> {
             ParseFn::And => self.and(),
             ParseFn::Or => self.or(),
             ParseFn::Call => self.call(),
+            ParseFn::Dot => self.dot(can_assign),
+            ParseFn::Index => self.index(can_assign),
+            ParseFn::List => self.list(),
+            ParseFn::OptionalDot => self.optional_dot(),
+            ParseFn::OptionalIndex => self.optional_index(),
+            ParseFn::This => self.this(),
             ParseFn::None => {}
         }
     }
@@ -512,8 +648,29 @@ impl<'a> Parser<'a> {
 
     fn number(&mut self) {
         trace!("parser::Parser::number()");
-        let num_str = self.span_to_str(self.previous.literal.clone());
-        let value = num_str.parse::<f64>().unwrap();
+        let literal = self
+            .span_to_str(self.previous.literal.clone())
+            .replace('_', "");
+
+        let radix = match literal.get(..2) {
+            Some("0x" | "0X") => 16,
+            Some("0o" | "0O") => 8,
+            Some("0b" | "0B") => 2,
+            _ => 10,
+        };
+
+        let value = if radix == 10 {
+            literal.parse::<f64>().ok()
+        } else {
+            u64::from_str_radix(&literal[2..], radix)
+                .ok()
+                .map(|value| value as f64)
+        };
+
+        let Some(value) = value else {
+            self.error_at_current("Number literal is out of range.");
+            return;
+        };
         self.emit_constant(Value::Number { value });
     }
 
@@ -523,6 +680,16 @@ impl<'a> Parser<'a> {
         self.consume(TokenType::RightParen, "Expected ')' after expression.");
     }
 
+    fn await_expression(&mut self) {
+        trace!("parser::Parser::await_expression()");
+        if !self.current_frame().function.is_async {
+            self.error_at_current("Can't use 'await' outside an async function.");
+        }
+
+        self.parse_precedence(Precedence::Unary);
+        self.emit_op(OpCode::Await);
+    }
+
     fn unary(&mut self) {
         trace!("parser::Parser::unary()");
         let operator_type = self.previous.token_type.clone();
@@ -554,6 +721,7 @@ impl<'a> Parser<'a> {
             TokenType::GreaterEqual => self.emit_ops(OpCode::Less, OpCode::Not),
             TokenType::Less => self.emit_op(OpCode::Less),
             TokenType::LessEqual => self.emit_ops(OpCode::Greater, OpCode::Not),
+            TokenType::Is => self.emit_op(OpCode::Is),
             _ => return,
         }
     }
@@ -571,12 +739,21 @@ impl<'a> Parser<'a> {
 
     fn string(&mut self) {
         trace!("parser::Parser::string()");
-        let literal = self.previous.literal.clone();
-        self.emit_constant(Value::String {
-            value: Rc::new(String::from(
-                &self.source[literal.start + 1..literal.end - 1],
-            )),
-        });
+        let text = self.span_to_str(self.previous.literal.clone());
+
+        // Backslashes are kept as written in every literal, so the `r`
+        // prefix only has to be stripped.
+        let text = text.strip_prefix('r').unwrap_or(text);
+
+        let is_triple = text.len() >= 6 && (text.starts_with("\"\"\"") || text.starts_with("'''"));
+        let value = if is_triple {
+            dedent(&text[3..text.len() - 3])
+        } else {
+            String::from(&text[1..text.len() - 1])
+        };
+
+        let value = self.interner.intern(&value);
+        self.emit_constant(Value::String { value });
     }
 
     fn variable(&mut self, can_assign: bool) {
@@ -608,17 +785,70 @@ impl<'a> Parser<'a> {
     fn call(&mut self) {
         trace!("parser::Parser::call()");
 
-        let arg_count = self.argument_list();
-        self.emit_ops_usize(OpCode::Call, arg_count);
+        match self.argument_list() {
+            Some(arg_count) => {
+                let offset = self.current_frame().function.chunk.len();
+                self.emit_op_operand(OpCode::Call, arg_count);
+                self.current_frame_mut().last_call = Some(offset);
+            }
+            None => {
+                let offset = self.current_frame().function.chunk.len();
+                self.emit_op(OpCode::CallSpread);
+                self.current_frame_mut().last_call = Some(offset);
+            }
+        }
+    }
+
+    fn list(&mut self) {
+        trace!("parser::Parser::list()");
+
+        if let Some(count) = self.element_list(TokenType::RightBracket) {
+            self.emit_op_operand(OpCode::BuildList, count);
+        }
+        self.consume(TokenType::RightBracket, "Expected ']' after list elements.");
     }
 
-    fn argument_list(&mut self) -> usize {
-        let mut arg_count = 0;
+    /// Compiles comma separated elements up to `closing`. Without a spread
+    /// the elements are left on the stack and their count is returned. Once
+    /// a `...` element shows up the elements are collected into a list
+    /// instead, and `None` tells the caller the list is on the stack.
+    fn element_list(&mut self, closing: TokenType) -> Option<usize> {
+        trace!("parser::Parser::element_list(closing: {:?})", closing);
+        let mut pending = 0;
+        let mut has_list = false;
 
-        if !self.check_type(TokenType::RightParen) {
+        if !self.check_type(closing) {
             loop {
-                arg_count += 1;
-                self.expression();
+                if self.match_token(TokenType::DotDotDot) {
+                    if has_list {
+                        if pending > 0 {
+                            self.emit_op_operand(OpCode::ExtendList, pending);
+                        }
+                    } else {
+                        self.emit_op_operand(OpCode::BuildList, pending);
+                        has_list = true;
+                    }
+                    pending = 0;
+
+                    self.expression();
+                    self.emit_op(OpCode::Spread);
+                } else {
+                    self.expression();
+                    pending += 1;
+
+                    // Counts are a single byte, so a long run of elements
+                    // is gathered into a list as if a spread had started.
+                    if pending == u8::MAX as usize {
+                        let op = if has_list {
+                            OpCode::ExtendList
+                        } else {
+                            OpCode::BuildList
+                        };
+                        self.emit_op_operand(op, pending);
+                        has_list = true;
+                        pending = 0;
+                    }
+                }
 
                 if !self.match_token(TokenType::Comma) {
                     break;
@@ -626,6 +856,108 @@ impl<'a> Parser<'a> {
             }
         }
 
+        if !has_list {
+            return Some(pending);
+        }
+
+        if pending > 0 {
+            self.emit_op_operand(OpCode::ExtendList, pending);
+        }
+        None
+    }
+
+    fn dot(&mut self, can_assign: bool) {
+        trace!("parser::Parser::dot()");
+
+        self.consume(TokenType::Identifier, "Expected property name after '.'.");
+        let name = self.identifier_constant(self.previous.literal.clone());
+
+        if can_assign && self.match_token(TokenType::Equal) {
+            self.expression();
+            self.emit_op_operand(OpCode::SetProperty, name);
+        } else {
+            self.emit_op_operand(OpCode::GetProperty, name);
+        }
+    }
+
+    fn index(&mut self, can_assign: bool) {
+        trace!("parser::Parser::index()");
+
+        self.expression();
+        self.consume(TokenType::RightBracket, "Expected ']' after index.");
+
+        if can_assign && self.match_token(TokenType::Equal) {
+            self.expression();
+            self.emit_op(OpCode::SetIndex);
+        } else {
+            self.emit_op(OpCode::GetIndex);
+        }
+    }
+
+    fn optional_dot(&mut self) {
+        trace!("parser::Parser::optional_dot()");
+
+        let nil_jump = self.emit_jump(OpCode::JumpIfNil);
+        self.dot(false);
+        self.optional_chain();
+        self.patch_jump(nil_jump);
+    }
+
+    fn optional_index(&mut self) {
+        trace!("parser::Parser::optional_index()");
+
+        let nil_jump = self.emit_jump(OpCode::JumpIfNil);
+        self.index(false);
+        self.optional_chain();
+        self.patch_jump(nil_jump);
+    }
+
+    /// Compiles the rest of a `?.`/`?[` chain inside the short-circuit so
+    /// `a?.b.c()` skips the whole chain, not only `.b`, when `a` is nil.
+    fn optional_chain(&mut self) {
+        trace!("parser::Parser::optional_chain()");
+
+        loop {
+            match self.current.token_type {
+                TokenType::Dot => {
+                    self.advance();
+                    self.dot(false);
+                }
+                TokenType::LeftParen => {
+                    self.advance();
+                    self.call();
+                }
+                TokenType::LeftBracket => {
+                    self.advance();
+                    self.index(false);
+                }
+                TokenType::QuestionDot => {
+                    self.advance();
+                    self.optional_dot();
+                }
+                TokenType::QuestionBracket => {
+                    self.advance();
+                    self.optional_index();
+                }
+                _ => break,
+            }
+        }
+    }
+
+    fn this(&mut self) {
+        trace!("parser::Parser::this()");
+
+        if !self.in_method() {
+            self.error_at_current("Can't use 'this' outside of a class.");
+            return;
+        }
+
+        self.named_variable(THIS_SPAN, false);
+    }
+
+    fn argument_list(&mut self) -> Option<usize> {
+        let arg_count = self.element_list(TokenType::RightParen);
+
         self.consume(TokenType::RightParen, "Expected ')' after arguments.");
 
         arg_count
@@ -647,7 +979,7 @@ impl<'a> Parser<'a> {
                 get_op = OpCode::GetUpvalue;
                 set_op = OpCode::SetUpvalue;
             } else {
-                arg = self.identifier_constant(name);
+                arg = self.global_slot(name);
                 get_op = OpCode::GetGlobal;
                 set_op = OpCode::SetGlobal;
             }
@@ -655,9 +987,9 @@ impl<'a> Parser<'a> {
 
         if can_assign && self.match_token(TokenType::Equal) {
             self.expression();
-            self.emit_ops_usize(set_op, arg);
+            self.emit_op_operand(set_op, arg);
         } else {
-            self.emit_ops_usize(get_op, arg);
+            self.emit_op_operand(get_op, arg);
         }
     }
 
@@ -714,7 +1046,7 @@ impl<'a> Parser<'a> {
             return 0;
         }
 
-        self.identifier_constant(self.previous.literal.clone())
+        self.global_slot(self.previous.literal.clone())
     }
 
     fn declare_variable(&mut self) {
@@ -751,18 +1083,27 @@ impl<'a> Parser<'a> {
     fn identifier_constant(&mut self, name: Span) -> usize {
         trace!("parser::Parser::identifier_constant(name: {:?})", name);
 
-        let source = &self.source[name.start..name.end];
+        let value = self.interner.intern(&self.source[name.start..name.end]);
         let frame = self.current_frame_mut();
-        frame.function.chunk.add_constant(Value::String {
-            value: Rc::new(String::from(source)),
-        })
+        frame.function.chunk.add_constant(Value::String { value })
+    }
+
+    fn global_slot(&mut self, name: Span) -> usize {
+        trace!("parser::Parser::global_slot(name: {:?})", name);
+
+        let name = self.interner.intern(&self.source[name.start..name.end]);
+        self.globals.slot(name)
     }
 
     fn declaration(&mut self) {
         trace!("parser::Parser::declaration()");
         match self.current.token_type {
-            TokenType::Fun => self.fun_declaration(),
+            TokenType::Fun => self.fun_declaration(false),
+            TokenType::Async => self.async_declaration(),
             TokenType::Var => self.var_declaration(),
+            TokenType::Enum => self.enum_declaration(),
+            TokenType::Class => self.class_declaration(),
+            TokenType::Trait => self.trait_declaration(),
             _ => self.statement(),
         }
 
@@ -771,19 +1112,179 @@ impl<'a> Parser<'a> {
         }
     }
 
-    fn fun_declaration(&mut self) {
-        trace!("parser::Parser::fun_declaration()");
+    fn class_declaration(&mut self) {
+        trace!("parser::Parser::class_declaration()");
+        self.advance();
+
+        let global = self.parse_variable("Expected class name.");
+        let class_name = self.previous.literal.clone();
+        let name_constant = self.identifier_constant(class_name.clone());
+
+        self.emit_op_operand(OpCode::Class, name_constant);
+        self.define_variable(global);
+
+        let mut traits: Vec<Span> = vec![];
+        if self.match_token(TokenType::With) {
+            loop {
+                self.consume(TokenType::Identifier, "Expected trait name after 'with'.");
+                traits.push(self.previous.literal.clone());
+
+                if !self.match_token(TokenType::Comma) {
+                    break;
+                }
+            }
+        }
+
+        self.named_variable(class_name, false);
+        self.consume(TokenType::LeftBrace, "Expected '{' before class body.");
+        while !self.check_type(TokenType::RightBrace) && !self.check_type(TokenType::Eof) {
+            self.method();
+        }
+        self.consume(TokenType::RightBrace, "Expected '}' after class body.");
+
+        // Traits are mixed in after the body so the class's own methods are
+        // already known and take precedence over trait methods.
+        for name in traits {
+            self.named_variable(name, false);
+            self.emit_op(OpCode::Implement);
+        }
+
+        self.emit_op(OpCode::Pop);
+    }
+
+    fn trait_declaration(&mut self) {
+        trace!("parser::Parser::trait_declaration()");
+        self.advance();
+
+        let global = self.parse_variable("Expected trait name.");
+        let trait_name = self.previous.literal.clone();
+        let name_constant = self.identifier_constant(trait_name.clone());
+
+        self.emit_op_operand(OpCode::Trait, name_constant);
+        self.define_variable(global);
+
+        self.named_variable(trait_name, false);
+        self.consume(TokenType::LeftBrace, "Expected '{' before trait body.");
+        while !self.check_type(TokenType::RightBrace) && !self.check_type(TokenType::Eof) {
+            self.consume(TokenType::Identifier, "Expected method name.");
+
+            if self.check_type(TokenType::Identifier) {
+                self.error_at_current("Traits can only contain methods.");
+                self.advance();
+            } else if self.span_to_str(self.previous.literal.clone()) == "init" {
+                self.error_at_current("Traits can't define an initializer.");
+            }
+
+            let constant = self.identifier_constant(self.previous.literal.clone());
+            self.function(FunctionType::Method, false);
+            self.emit_op_operand(OpCode::Method, constant);
+        }
+        self.consume(TokenType::RightBrace, "Expected '}' after trait body.");
+        self.emit_op(OpCode::Pop);
+    }
+
+    fn method(&mut self) {
+        trace!("parser::Parser::method()");
+
+        let is_async = self.match_token(TokenType::Async);
+        self.consume(TokenType::Identifier, "Expected method name.");
+
+        // `static`, `get` and `set` are only modifiers when another name
+        // follows, so methods may still be called `get` or `set`.
+        if self.check_type(TokenType::Identifier) && !is_async {
+            match self.span_to_str(self.previous.literal.clone()) {
+                "static" => return self.static_member(),
+                "get" => return self.accessor(FunctionType::Getter, OpCode::Getter),
+                "set" => return self.accessor(FunctionType::Setter, OpCode::Setter),
+                _ => {}
+            }
+        }
+
+        let constant = self.identifier_constant(self.previous.literal.clone());
+
+        let function_type = if self.span_to_str(self.previous.literal.clone()) == "init" {
+            if is_async {
+                self.error_at_current("Initializers can't be async.");
+            }
+            FunctionType::Initializer
+        } else {
+            FunctionType::Method
+        };
+
+        self.function(function_type, is_async);
+        self.emit_op_operand(OpCode::Method, constant);
+    }
+
+    fn accessor(&mut self, function_type: FunctionType, op: OpCode) {
+        trace!(
+            "parser::Parser::accessor(function_type: {:?})",
+            function_type
+        );
+
+        self.advance();
+        let constant = self.identifier_constant(self.previous.literal.clone());
+
+        self.function(function_type, false);
+        self.emit_op_operand(op, constant);
+    }
+
+    fn static_member(&mut self) {
+        trace!("parser::Parser::static_member()");
+
+        self.advance();
+        let constant = self.identifier_constant(self.previous.literal.clone());
+
+        if self.check_type(TokenType::LeftParen) {
+            self.function(FunctionType::Method, false);
+            self.emit_op_operand(OpCode::StaticMethod, constant);
+            return;
+        }
+
+        if self.match_token(TokenType::Equal) {
+            self.expression();
+        } else {
+            self.emit_op(OpCode::Nil);
+        }
+        self.consume(TokenType::Semi, "Expected ';' after static field.");
+        self.emit_op_operand(OpCode::StaticField, constant);
+    }
+
+    fn in_method(&self) -> bool {
+        self.frames[..=self.frame_index].iter().any(|frame| {
+            matches!(
+                frame.function_type,
+                FunctionType::Method
+                    | FunctionType::Initializer
+                    | FunctionType::Getter
+                    | FunctionType::Setter
+            )
+        })
+    }
+
+    fn async_declaration(&mut self) {
+        trace!("parser::Parser::async_declaration()");
+        self.advance();
+
+        if !self.check_type(TokenType::Fun) {
+            self.error_at_current("Expected 'fun' after 'async'.");
+            return;
+        }
+        self.fun_declaration(true);
+    }
+
+    fn fun_declaration(&mut self, is_async: bool) {
+        trace!("parser::Parser::fun_declaration(is_async: {is_async})");
         self.advance();
 
         let global = self.parse_variable("Expected function name after fun.");
         self.mark_initialized();
-        self.function(FunctionType::Function);
+        self.function(FunctionType::Function, is_async);
         self.define_variable(global);
     }
 
-    fn function(&mut self, function_type: FunctionType) {
+    fn function(&mut self, function_type: FunctionType, is_async: bool) {
         trace!(
-            "parser::Parser::function(function_type: {:?})",
+            "parser::Parser::function(function_type: {:?}, is_async: {is_async})",
             function_type
         );
 
@@ -801,32 +1302,50 @@ impl<'a> Parser<'a> {
         );
         self.frames.push(frame);
         self.frame_index += 1;
-
+        self.current_frame_mut().function.is_async = is_async;
+
+        let slot_zero = match self.current_frame().function_type {
+            FunctionType::Method
+            | FunctionType::Initializer
+            | FunctionType::Getter
+            | FunctionType::Setter => THIS_SPAN,
+            _ => Span::new(0, 0),
+        };
         self.current_frame_mut()
             .locals
-            .push(Local::new(Span::new(0, 0), 0, false));
+            .push(Local::new(slot_zero, 0, false));
 
         self.begin_scope();
 
-        self.consume(TokenType::LeftParen, "Expected '(' after function name.");
-        if !self.check_type(TokenType::RightParen) {
-            loop {
-                let frame = self.current_frame_mut();
+        // Getters are declared without a parameter list.
+        if self.current_frame().function_type != FunctionType::Getter {
+            self.consume(TokenType::LeftParen, "Expected '(' after function name.");
+            if !self.check_type(TokenType::RightParen) {
+                loop {
+                    let frame = self.current_frame_mut();
 
-                frame.function.arity += 1;
+                    frame.function.arity += 1;
 
-                let constant = self.parse_variable("Expected parameter name.");
-                self.define_variable(constant);
+                    let constant = self.parse_variable("Expected parameter name.");
+                    self.define_variable(constant);
 
-                if !self.match_token(TokenType::Comma) {
-                    break;
+                    if !self.match_token(TokenType::Comma) {
+                        break;
+                    }
                 }
             }
+            self.consume(
+                TokenType::RightParen,
+                "Expected ')' after function parameters.",
+            );
         }
-        self.consume(
-            TokenType::RightParen,
-            "Expected ')' after function parameters.",
-        );
+
+        if self.current_frame().function_type == FunctionType::Setter
+            && self.current_frame().function.arity != 1
+        {
+            self.error_at_current("Setters take exactly one parameter.");
+        }
+
         self.consume(TokenType::LeftBrace, "Expected '{' before function body.");
 
         while !self.check_type(TokenType::RightBrace) && !self.check_type(TokenType::Eof) {
@@ -846,7 +1365,15 @@ impl<'a> Parser<'a> {
             }
         }
 
-        self.emit_op(OpCode::Return);
+        self.end_function();
+    }
+
+    /// Finishes the innermost compile frame and emits the `OP_CLOSURE` that
+    /// instantiates it in the enclosing frame.
+    fn end_function(&mut self) {
+        trace!("parser::Parser::end_function()");
+        self.emit_return();
+        self.optimize_frame();
 
         let frame = self
             .frames
@@ -860,23 +1387,75 @@ impl<'a> Parser<'a> {
             ..
         } = frame;
 
-        let line = self.previous.line;
-        let col = self.previous.col;
         let function = &mut self.current_frame_mut().function;
         let pos = function.chunk.add_constant(Value::Function {
-            value: inner_function,
+            value: Rc::new(inner_function),
         });
-        function.chunk.write(OpCode::Closure as usize, line, col);
-        function.chunk.write(pos, line, col);
+        self.emit_op_operand(OpCode::Closure, pos);
 
         for upvalue in upvalues {
-            if upvalue.is_local {
-                self.emit_op(OpCode::True);
-            } else {
-                self.emit_op(OpCode::False);
+            let mut flags = if upvalue.is_local { UPVALUE_LOCAL } else { 0 };
+            match u8::try_from(upvalue.index) {
+                Ok(index) => {
+                    self.emit_byte(flags);
+                    self.emit_byte(index);
+                }
+                Err(_) => {
+                    flags |= UPVALUE_LONG;
+                    self.emit_byte(flags);
+                    self.emit_long(upvalue.index);
+                }
+            }
+        }
+    }
+
+    fn enum_declaration(&mut self) {
+        trace!("parser::Parser::enum_declaration()");
+        self.advance();
+
+        let global = self.parse_variable("Expected enum name after enum.");
+        let name = self.span_to_str(self.previous.literal.clone()).to_string();
+        self.mark_initialized();
+
+        self.consume(TokenType::LeftBrace, "Expected '{' before enum body.");
+
+        let mut variants: Vec<EnumVariant> = vec![];
+        while !self.check_type(TokenType::RightBrace) && !self.check_type(TokenType::Eof) {
+            self.consume(TokenType::Identifier, "Expected variant name.");
+            let variant_name = self.span_to_str(self.previous.literal.clone()).to_string();
+
+            if variants.iter().any(|variant| variant.name == variant_name) {
+                self.error_at_current("Already a variant with this name in this enum.");
+            }
+
+            let mut arity = 0;
+            if self.match_token(TokenType::LeftParen) {
+                if !self.check_type(TokenType::RightParen) {
+                    loop {
+                        self.consume(TokenType::Identifier, "Expected field name.");
+                        arity += 1;
+
+                        if !self.match_token(TokenType::Comma) {
+                            break;
+                        }
+                    }
+                }
+                self.consume(TokenType::RightParen, "Expected ')' after variant fields.");
+            }
+
+            variants.push(EnumVariant::new(variant_name, arity));
+
+            if !self.match_token(TokenType::Comma) {
+                break;
             }
-            self.emit_op_usize(upvalue.index);
         }
+
+        self.consume(TokenType::RightBrace, "Expected '}' after enum body.");
+
+        self.emit_constant(Value::Enum {
+            value: Rc::new(Enum::new(name, variants)),
+        });
+        self.define_variable(global);
     }
 
     fn var_declaration(&mut self) {
@@ -903,8 +1482,7 @@ impl<'a> Parser<'a> {
             return;
         }
 
-        self.emit_op(OpCode::DefineGlobal);
-        self.emit_op_usize(var);
+        self.emit_op_operand(OpCode::DefineGlobal, var);
     }
 
     fn mark_initialized(&mut self) {
@@ -922,10 +1500,13 @@ impl<'a> Parser<'a> {
         trace!("parser::Parser::statement()");
         match self.current.token_type {
             TokenType::Print => self.print_statement(),
+            TokenType::Assert => self.assert_statement(),
             TokenType::If => self.if_statement(),
             TokenType::Return => self.return_statement(),
             TokenType::While => self.while_statement(),
             TokenType::For => self.for_statement(),
+            TokenType::Case => self.case_statement(),
+            TokenType::Defer => self.defer_statement(),
             TokenType::LeftBrace => self.block(),
             _ => self.expression_statement(),
         }
@@ -934,9 +1515,132 @@ impl<'a> Parser<'a> {
     fn print_statement(&mut self) {
         trace!("parser::Parser::print_statement()");
         self.advance();
-        self.expression();
+
+        // Options are only looked for after a value and a comma, so
+        // `print sep = 5;` stays an assignment.
+        let mut count = 0;
+        let mut has_options = false;
+        loop {
+            self.expression();
+            count += 1;
+            if count == u8::MAX as usize + 1 {
+                self.error_at_current("Can't print more than 255 values.");
+            }
+
+            if !self.match_token(TokenType::Comma) {
+                break;
+            }
+
+            if self.print_option().is_some() {
+                has_options = true;
+                break;
+            }
+        }
+
+        // `sep = ...` and `end = ...` may follow the values in either order.
+        let mut separator = None;
+        let mut end = None;
+        while has_options && let Some(option) = self.print_option() {
+            self.advance();
+            self.advance();
+
+            let start = self.current_frame().function.chunk.len();
+            self.expression();
+            let code = self.current_frame_mut().function.chunk.split_off(start);
+
+            let slot = if option == "sep" {
+                &mut separator
+            } else {
+                &mut end
+            };
+            if slot.replace(code).is_some() {
+                self.error_at_current(format!("Duplicate '{option}' option in print.").as_str());
+            }
+
+            if !self.match_token(TokenType::Comma) {
+                break;
+            }
+        }
         self.consume(TokenType::Semi, "Expected ';' after value.");
-        self.emit_op(OpCode::Print);
+
+        if separator.is_none() && end.is_none() {
+            self.emit_op_operand(OpCode::Print, count);
+            return;
+        }
+
+        for code in [separator, end] {
+            match code {
+                Some(code) => self.current_frame_mut().function.chunk.append(code),
+                None => self.emit_op(OpCode::Nil),
+            }
+        }
+        self.emit_op_operand(OpCode::PrintWith, count);
+    }
+
+    /// Returns `sep` or `end` when the upcoming tokens are a print option
+    /// such as `sep = ", "`.
+    fn print_option(&mut self) -> Option<&'static str> {
+        if !self.check_type(TokenType::Identifier) {
+            return None;
+        }
+
+        let followed_by_equal = matches!(
+            self.tokens.peek(),
+            Some(Ok(token)) if token.token_type == TokenType::Equal
+        );
+        if !followed_by_equal {
+            return None;
+        }
+
+        match self.span_to_str(self.current.literal.clone()) {
+            "sep" => Some("sep"),
+            "end" => Some("end"),
+            _ => None,
+        }
+    }
+
+    fn assert_statement(&mut self) {
+        trace!("parser::Parser::assert_statement()");
+        self.advance();
+        let keyword = self.previous.clone();
+        let start_offset = self.current_frame().function.chunk.len();
+
+        let start = self.current.literal.start;
+        self.expression();
+        let end = self.previous.literal.end;
+        let text = self.source.get(start..end).unwrap_or_default().to_string();
+
+        let fail_jump = self.emit_jump(OpCode::JumpIfFalse);
+        self.emit_op(OpCode::Pop);
+        let end_jump = self.emit_jump(OpCode::Jump);
+
+        // The message is only evaluated once the assertion has failed.
+        self.patch_jump(fail_jump);
+        self.emit_op(OpCode::Pop);
+        if self.match_token(TokenType::Comma) {
+            self.expression();
+        } else {
+            self.emit_op(OpCode::Nil);
+        }
+        self.consume(TokenType::Semi, "Expected ';' after assertion.");
+
+        let text = self.interner.intern(&text);
+        let pos = self
+            .current_frame_mut()
+            .function
+            .chunk
+            .add_constant(Value::String { value: text });
+        // Failures are reported where the assertion starts, not at the `;`.
+        self.emit_at(keyword, |parser| {
+            parser.emit_op_operand(OpCode::Assert, pos)
+        });
+        self.patch_jump(end_jump);
+
+        if self.no_asserts {
+            let frame = self.current_frame_mut();
+            frame.function.chunk.truncate(start_offset);
+            frame.last_call = None;
+        }
     }
 
     fn block(&mut self) {
@@ -1002,7 +1706,9 @@ impl<'a> Parser<'a> {
     fn identifier_equal(&self, a: Span, b: Span) -> bool {
         trace!("parser::Parser::identifier_equal(a: {:?}, b: {:?})", a, b);
 
-        if a.end - a.start != b.end - b.start {
+        if a == THIS_SPAN || b == THIS_SPAN {
+            self.local_name(&a) == self.local_name(&b)
+        } else if a.end - a.start != b.end - b.start {
             false
         } else if self.source[a.start..a.end] == self.source[b.start..b.end] {
             true
@@ -1011,6 +1717,14 @@ impl<'a> Parser<'a> {
         }
     }
 
+    fn local_name(&self, span: &Span) -> &str {
+        if *span == THIS_SPAN {
+            "this"
+        } else {
+            &self.source[span.start..span.end]
+        }
+    }
+
     fn resolve_local(&mut self, name: Span) -> usize {
         trace!("parser::Parser::resolve_local(name: {:?})", name);
         let mut error = false;
@@ -1073,10 +1787,34 @@ impl<'a> Parser<'a> {
         }
 
         if self.match_token(TokenType::Semi) {
-            self.emit_ops(OpCode::Nil, OpCode::Return);
+            self.emit_return();
         } else {
+            match self.current_frame().function_type {
+                FunctionType::Initializer => {
+                    self.error_at_current("Can't return a value from an initializer.")
+                }
+                FunctionType::Setter => {
+                    self.error_at_current("Can't return a value from a setter.")
+                }
+                _ => {}
+            }
+
             self.expression();
             self.consume(TokenType::Semi, "Expected ';' after return value.");
+
+            // A call that is the last instruction before the return is in
+            // tail position and can reuse the current call frame.
+            let frame = self.current_frame_mut();
+            let chunk = &mut frame.function.chunk;
+            if let Some(offset) = frame.last_call
+                && offset + chunk.instruction_len(offset) == chunk.len()
+            {
+                chunk.instructions[offset] = match OpCode::from_u8(chunk.instructions[offset]) {
+                    OpCode::CallSpread => OpCode::TailCallSpread,
+                    _ => OpCode::TailCall,
+                } as u8;
+            }
+
             self.emit_op(OpCode::Return);
         }
     }
@@ -1133,33 +1871,176 @@ impl<'a> Parser<'a> {
         self.end_scope();
     }
 
+    fn defer_statement(&mut self) {
+        trace!("parser::Parser::defer_statement()");
+        self.advance();
+
+        // The deferred code becomes a closure so it reads the function's
+        // variables as they are when the function returns.
+        let frame = CompileFrame::new(
+            Function::new(0, Chunk::new(), Some(String::from("defer")), 0),
+            FunctionType::Function,
+            vec![],
+            vec![],
+            self.current_frame().scope_depth,
+        );
+        self.frames.push(frame);
+        self.frame_index += 1;
+
+        self.current_frame_mut()
+            .locals
+            .push(Local::new(Span::new(0, 0), 0, false));
+
+        // `defer expr;` compiles as an expression statement; any other
+        // statement, such as `defer print x;` or a block, works as well.
+        self.statement();
+
+        self.end_function();
+        self.emit_op(OpCode::Defer);
+    }
+
+    fn case_statement(&mut self) {
+        trace!("parser::Parser::case_statement()");
+        self.advance();
+
+        self.begin_scope();
+
+        self.consume(TokenType::LeftParen, "Expected '(' after 'case'.");
+        self.expression();
+        self.consume(TokenType::RightParen, "Expected ')' after case value.");
+
+        // The scrutinee lives in a hidden local so every arm can re-read it.
+        self.add_local(Span::new(0, 0));
+        self.mark_initialized();
+        let scrutinee = self.current_frame().locals.len() - 1;
+
+        self.consume(TokenType::LeftBrace, "Expected '{' before case arms.");
+
+        let mut end_jumps = vec![];
+        while !self.check_type(TokenType::RightBrace) && !self.check_type(TokenType::Eof) {
+            if self.match_token(TokenType::Else) {
+                self.case_body();
+                break;
+            }
+
+            self.emit_op_operand(OpCode::GetLocal, scrutinee);
+
+            self.consume(TokenType::Identifier, "Expected enum variant in case arm.");
+            self.named_variable(self.previous.literal.clone(), false);
+            while self.match_token(TokenType::Dot) {
+                self.dot(false);
+            }
+
+            let mut bindings: Vec<Span> = vec![];
+            if self.match_token(TokenType::LeftParen) {
+                if !self.check_type(TokenType::RightParen) {
+                    loop {
+                        self.consume(TokenType::Identifier, "Expected binding name.");
+                        let binding = self.previous.literal.clone();
+
+                        if bindings
+                            .iter()
+                            .any(|b| self.identifier_equal(b.clone(), binding.clone()))
+                        {
+                            self.error_at_current("Already a binding with this name in this arm.");
+                        }
+                        bindings.push(binding);
+                        if bindings.len() == u8::MAX as usize + 1 {
+                            self.error_at_current("Can't have more than 255 bindings.");
+                        }
+
+                        if !self.match_token(TokenType::Comma) {
+                            break;
+                        }
+                    }
+                }
+                self.consume(TokenType::RightParen, "Expected ')' after bindings.");
+            }
+
+            self.emit_op_operand(OpCode::MatchVariant, bindings.len());
+            let next_arm = self.emit_jump(OpCode::JumpIfFalse);
+            self.emit_op(OpCode::Pop);
+
+            self.begin_scope();
+            self.emit_op_operand(OpCode::GetLocal, scrutinee);
+            self.emit_op(OpCode::Destructure);
+            for binding in bindings {
+                self.add_local(binding);
+                self.mark_initialized();
+            }
+            self.case_body();
+            self.end_scope();
+
+            end_jumps.push(self.emit_jump(OpCode::Jump));
+            self.patch_jump(next_arm);
+            self.emit_op(OpCode::Pop);
+        }
+
+        for end_jump in end_jumps {
+            self.patch_jump(end_jump);
+        }
+
+        self.consume(TokenType::RightBrace, "Expected '}' after case arms.");
+
+        self.end_scope();
+    }
+
+    fn case_body(&mut self) {
+        trace!("parser::Parser::case_body()");
+        if self.check_type(TokenType::LeftBrace) {
+            self.block();
+        } else {
+            self.error_at_current("Expected '{' before case arm body.");
+        }
+    }
+
     fn emit_loop(&mut self, loop_start: usize) {
         trace!("parser::Parser::emit_loop(loop_start: {loop_start})");
 
         self.emit_op(OpCode::Loop);
 
+        // The offset is taken from just past its own two bytes.
         let frame = self.current_frame();
-        let offset = frame.function.chunk.len() - loop_start;
+        let offset = frame.function.chunk.len() - loop_start + 2;
+        let Ok(offset) = u16::try_from(offset) else {
+            self.error_at_current("Loop body too large.");
+            return;
+        };
 
-        self.emit_op_usize(offset);
+        for byte in offset.to_be_bytes() {
+            self.emit_byte(byte);
+        }
     }
 }
 
 impl<'a> Parser<'a> {
     fn emit_op(&mut self, op: OpCode) {
         trace!("parser::Parser::emit_op(op: {:?})", op);
-        let line = self.previous.line;
-        let col = self.previous.col;
-        let function = &mut self.current_frame_mut().function;
-        function.chunk.write(op as usize, line, col);
+        self.emit_byte(op as u8);
+    }
+
+    /// Runs `emit` with `token` standing in for the previous token, so the
+    /// bytes it emits carry `token`'s location.
+    fn emit_at(&mut self, token: Token, emit: impl FnOnce(&mut Self)) {
+        let previous = std::mem::replace(&mut self.previous, token);
+        emit(self);
+        self.previous = previous;
     }
 
-    fn emit_op_usize(&mut self, op: usize) {
-        trace!("parser::Parser::emit_op(op: {op})");
+    fn emit_byte(&mut self, byte: u8) {
+        trace!("parser::Parser::emit_byte(byte: {byte})");
         let line = self.previous.line;
         let col = self.previous.col;
         let function = &mut self.current_frame_mut().function;
-        function.chunk.write(op, line, col);
+        function.chunk.write(byte, line, col);
+    }
+
+    /// Emits the three byte operand of a `*_LONG` instruction.
+    fn emit_long(&mut self, operand: usize) {
+        trace!("parser::Parser::emit_long(operand: {operand})");
+        for byte in &(operand as u32).to_be_bytes()[1..] {
+            self.emit_byte(*byte);
+        }
     }
 
     fn emit_ops(&mut self, op1: OpCode, op2: OpCode) {
@@ -1168,35 +2049,68 @@ impl<'a> Parser<'a> {
         self.emit_op(op2);
     }
 
-    fn emit_ops_usize(&mut self, op1: OpCode, op2: usize) {
-        trace!("parser::Parser::emit_ops_usize(op1: {:?}, op2: {op2})", op1);
-        self.emit_op(op1);
-        self.emit_op_usize(op2);
+    /// Emits `op` with a one byte operand, or its `*_LONG` variant when the
+    /// operand doesn't fit in a byte.
+    fn emit_op_operand(&mut self, op: OpCode, operand: usize) {
+        trace!(
+            "parser::Parser::emit_op_operand(op: {:?}, operand: {operand})",
+            op
+        );
+        if let Ok(byte) = u8::try_from(operand) {
+            self.emit_op(op);
+            self.emit_byte(byte);
+            return;
+        }
+
+        match op.long() {
+            Some(long) if operand <= MAX_LONG_OPERAND => {
+                self.emit_op(long);
+                self.emit_long(operand);
+            }
+            _ => self.error_at_current("Too many values for one instruction."),
+        }
+    }
+
+    fn emit_return(&mut self) {
+        trace!("parser::Parser::emit_return()");
+        match self.current_frame().function_type {
+            FunctionType::Initializer => self.emit_op_operand(OpCode::GetLocal, 0),
+            // Assignments evaluate to the assigned value, even through a setter.
+            FunctionType::Setter => self.emit_op_operand(OpCode::GetLocal, 1),
+            _ => self.emit_op(OpCode::Nil),
+        }
+        self.emit_op(OpCode::Return);
     }
 
     fn emit_constant(&mut self, value: Value) {
         trace!("parser::Parser::emit_constant(value: {value})");
-        let line = self.previous.line;
-        let col = self.previous.col;
         let function = &mut self.current_frame_mut().function;
         let pos = function.chunk.add_constant(value);
-        function.chunk.write(OpCode::Constant as usize, line, col);
-        function.chunk.write(pos, line, col);
+        self.emit_op_operand(OpCode::Constant, pos);
     }
 
+    /// Emits a jump with a placeholder two byte offset and returns where the
+    /// offset is, for `patch_jump`.
     fn emit_jump(&mut self, op: OpCode) -> usize {
         trace!("parser::Parser::emit_jump({:?})", op);
         self.emit_op(op);
-        self.emit_op_usize(usize::MAX);
+        self.emit_byte(u8::MAX);
+        self.emit_byte(u8::MAX);
         let function = &mut self.current_frame_mut().function;
-        function.chunk.len() - 1
+        function.chunk.len() - 2
     }
 
     fn patch_jump(&mut self, offset: usize) {
         trace!("parser::Parser::patch_jump(offset: {offset})");
-        // Distance from the operand to the next instruction after the jump target
+        // Distance from the end of the operand to the jump target
+        let function = &mut self.current_frame_mut().function;
+        let jump = function.chunk.len() - offset - 2;
+        let Ok(jump) = u16::try_from(jump) else {
+            self.error_at_current("Too much code to jump over.");
+            return;
+        };
+
         let function = &mut self.current_frame_mut().function;
-        let jump = function.chunk.len() - offset - 1;
-        function.chunk.instructions[offset] = jump;
+        function.chunk.instructions[offset..offset + 2].copy_from_slice(&jump.to_be_bytes());
     }
 }
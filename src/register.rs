@@ -0,0 +1,687 @@
+use std::cmp::Ordering;
+use std::rc::Rc;
+
+use crate::function::Function;
+use crate::value::Value;
+use crate::vm::{Chunk, OpCode, UPVALUE_LOCAL, UPVALUE_LONG, decode_operand};
+
+/// Where an instruction takes a value from. Registers are numbered like
+/// the frame's stack slots, so the first ones are the callee, parameters
+/// and locals, and temporaries come after them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operand {
+    Register(usize),
+    Constant(usize),
+    Nil,
+    Bool(bool),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UnaryOp {
+    Negate,
+    Not,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinaryOp {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Equal,
+    NotEqual,
+    Greater,
+    GreaterEqual,
+    Less,
+    LessEqual,
+}
+
+impl BinaryOp {
+    /// The result for two numbers, computed as the stack engine does. The
+    /// negated orderings are "not less" and "not greater", so NaN compares
+    /// the same way too.
+    #[inline]
+    pub fn numbers(self, a: f64, b: f64) -> Value {
+        match self {
+            Self::Add => Value::Number { value: a + b },
+            Self::Subtract => Value::Number { value: a - b },
+            Self::Multiply => Value::Number { value: a * b },
+            Self::Divide => Value::Number { value: a / b },
+            Self::Equal => Value::Bool { value: a == b },
+            Self::NotEqual => Value::Bool { value: a != b },
+            Self::Greater => Value::Bool { value: a > b },
+            Self::GreaterEqual => Value::Bool {
+                value: a.partial_cmp(&b) != Some(Ordering::Less),
+            },
+            Self::Less => Value::Bool { value: a < b },
+            Self::LessEqual => Value::Bool {
+                value: a.partial_cmp(&b) != Some(Ordering::Greater),
+            },
+        }
+    }
+}
+
+/// A three-address instruction. Jumps hold the index of the instruction
+/// they land on.
+#[derive(Debug, Clone)]
+pub enum Instruction {
+    Move {
+        dst: usize,
+        src: Operand,
+    },
+    Unary {
+        op: UnaryOp,
+        dst: usize,
+        src: Operand,
+    },
+    Binary {
+        op: BinaryOp,
+        dst: usize,
+        a: Operand,
+        b: Operand,
+    },
+    Jump {
+        target: usize,
+    },
+    JumpIfFalse {
+        cond: usize,
+        target: usize,
+    },
+    DefineGlobal {
+        slot: usize,
+        src: Operand,
+    },
+    GetGlobal {
+        dst: usize,
+        slot: usize,
+    },
+    SetGlobal {
+        slot: usize,
+        src: Operand,
+    },
+    GetUpvalue {
+        dst: usize,
+        index: usize,
+    },
+    SetUpvalue {
+        index: usize,
+        src: Operand,
+    },
+    /// `constant` is the property's name.
+    GetProperty {
+        dst: usize,
+        object: Operand,
+        constant: usize,
+    },
+    SetProperty {
+        dst: usize,
+        object: Operand,
+        value: Operand,
+        constant: usize,
+    },
+    /// Closes the upvalues of a local going out of scope.
+    CloseUpvalue {
+        register: usize,
+    },
+    /// Each capture is whether it is a local of this frame, and its slot
+    /// or the index of this closure's upvalue.
+    Closure {
+        dst: usize,
+        constant: usize,
+        captures: Vec<(bool, usize)>,
+    },
+    /// The callee and its arguments are in consecutive registers from
+    /// `callee`, and the result replaces the callee.
+    Call {
+        callee: usize,
+        arg_count: usize,
+    },
+    TailCall {
+        callee: usize,
+        arg_count: usize,
+    },
+    Print {
+        start: usize,
+        count: usize,
+    },
+    Return {
+        src: Operand,
+    },
+}
+
+impl Instruction {
+    /// The register an instruction writes, if writing it is all it does
+    /// to the frame.
+    fn dst_mut(&mut self) -> Option<&mut usize> {
+        match self {
+            Self::Move { dst, .. }
+            | Self::Unary { dst, .. }
+            | Self::Binary { dst, .. }
+            | Self::GetGlobal { dst, .. }
+            | Self::GetUpvalue { dst, .. }
+            | Self::GetProperty { dst, .. }
+            | Self::SetProperty { dst, .. } => Some(dst),
+            _ => None,
+        }
+    }
+}
+
+/// A function lowered for the register engine.
+#[derive(Debug)]
+pub struct RegisterCode {
+    pub instructions: Vec<Instruction>,
+    /// For each instruction, the end of the stack instruction it came
+    /// from, which is where the stack engine's cursor would be. Errors and
+    /// stack traces use it, so they report the same locations.
+    pub offsets: Vec<usize>,
+    /// How many slots the frame needs: its locals and temporaries.
+    pub register_count: usize,
+}
+
+/// Lowers `function` and the functions in its constants for the register
+/// engine. A function using an instruction the lowering doesn't handle,
+/// or an async one, is left without register code and runs on the stack
+/// engine.
+pub fn lower(function: &mut Function) {
+    trace!("register::lower(function: {:?})", function.name);
+    for constant in function.chunk.constants.iter_mut() {
+        if let Value::Function { value } = constant
+            && let Some(inner) = Rc::get_mut(value)
+        {
+            lower(inner);
+        }
+    }
+
+    if !function.is_async {
+        function.register_code = Lowering::new(&function.chunk, function.arity).run();
+    }
+}
+
+fn binary_op(op: OpCode) -> Option<BinaryOp> {
+    match op {
+        OpCode::Add => Some(BinaryOp::Add),
+        OpCode::Subtract => Some(BinaryOp::Subtract),
+        OpCode::Multiply => Some(BinaryOp::Multiply),
+        OpCode::Divide => Some(BinaryOp::Divide),
+        OpCode::Equal => Some(BinaryOp::Equal),
+        OpCode::NotEqual => Some(BinaryOp::NotEqual),
+        OpCode::Greater => Some(BinaryOp::Greater),
+        OpCode::GreaterEqual => Some(BinaryOp::GreaterEqual),
+        OpCode::Less => Some(BinaryOp::Less),
+        OpCode::LessEqual => Some(BinaryOp::LessEqual),
+        _ => None,
+    }
+}
+
+/// Walks the stack bytecode keeping track of what each stack slot holds.
+/// Values that are only copied, such as a local or a constant pushed for
+/// an operator, aren't moved into their slot unless they have to be, and
+/// the instruction that uses them reads them where they are.
+struct Lowering<'a> {
+    chunk: &'a Chunk,
+    code: Vec<Instruction>,
+    offsets: Vec<usize>,
+    /// What each stack slot holds: its own register once the value is in
+    /// it, or the operand the value will be copied from.
+    stack: Vec<Operand>,
+    register_count: usize,
+    /// Whether control can reach the instruction being lowered from the
+    /// one before it.
+    reachable: bool,
+    /// Where the current straight run of code starts.
+    block_start: usize,
+    /// End of the stack instruction being lowered.
+    end: usize,
+    /// Indexed by stack offset: whether a jump lands there, the stack
+    /// depth it lands with, and the first register instruction there.
+    landing: Vec<bool>,
+    depths: Vec<Option<usize>>,
+    labels: Vec<Option<usize>>,
+    /// Register instructions whose targets are still stack offsets.
+    jumps: Vec<(usize, usize)>,
+}
+
+impl<'a> Lowering<'a> {
+    fn new(chunk: &'a Chunk, arity: usize) -> Self {
+        let len = chunk.instructions.len();
+        Self {
+            chunk,
+            code: vec![],
+            offsets: vec![],
+            stack: (0..=arity).map(Operand::Register).collect(),
+            register_count: arity + 1,
+            reachable: true,
+            block_start: 0,
+            end: 0,
+            landing: vec![false; len],
+            depths: vec![None; len],
+            labels: vec![None; len],
+            jumps: vec![],
+        }
+    }
+
+    fn run(mut self) -> Option<RegisterCode> {
+        let chunk = self.chunk;
+        let instructions = &chunk.instructions;
+
+        // Jump targets first, so no value is left in flight where control
+        // can arrive from elsewhere. The verifier made sure the offsets
+        // are sound.
+        let mut offset = 0;
+        while offset < instructions.len() {
+            let op = OpCode::from_u8(instructions[offset]);
+            let end = offset + chunk.instruction_len(offset);
+            if let Some(target) = self.target(op, offset, end) {
+                self.landing[target] = true;
+            }
+            offset = end;
+        }
+
+        let mut offset = 0;
+        while offset < instructions.len() {
+            let op = OpCode::from_u8(instructions[offset]);
+            let end = offset + chunk.instruction_len(offset);
+            self.end = end;
+            if self.landing[offset] {
+                self.land(offset)?;
+            }
+
+            // `OP_SET_LOCAL, OP_POP` is one move, and often none at all.
+            if matches!(op, OpCode::SetLocal | OpCode::SetLocalLong)
+                && instructions.get(end) == Some(&(OpCode::Pop as u8))
+                && !self.landing[end]
+                && !self.landing[offset]
+            {
+                let slot = decode_operand(&instructions[offset + 1..end]);
+                self.set_local_and_pop(slot)?;
+                offset = end + 1;
+                continue;
+            }
+
+            self.lower(op, offset, end)?;
+            offset = end;
+        }
+
+        for (index, target) in std::mem::take(&mut self.jumps) {
+            let label = self.labels[target]?;
+            match &mut self.code[index] {
+                Instruction::Jump { target } | Instruction::JumpIfFalse { target, .. } => {
+                    *target = label
+                }
+                _ => unreachable!("only jumps are patched"),
+            }
+        }
+
+        Some(RegisterCode {
+            instructions: self.code,
+            offsets: self.offsets,
+            register_count: self.register_count,
+        })
+    }
+
+    /// Where the jump at `offset` lands, if it is one.
+    fn target(&self, op: OpCode, offset: usize, end: usize) -> Option<usize> {
+        if !op.is_jump() {
+            return None;
+        }
+        op.jump_target(end, &self.chunk.instructions[offset + 1..end])
+    }
+
+    fn lower(&mut self, op: OpCode, offset: usize, end: usize) -> Option<()> {
+        let chunk = self.chunk;
+        let operands = &chunk.instructions[offset + 1..end];
+
+        if let Some(op) = binary_op(op) {
+            let b = self.stack.pop()?;
+            let a = self.stack.pop()?;
+            self.settle();
+            let dst = self.stack.len();
+            self.emit(Instruction::Binary { op, dst, a, b });
+            self.push(Operand::Register(dst));
+            return Some(());
+        }
+
+        match op {
+            OpCode::Constant | OpCode::ConstantLong => {
+                self.push(Operand::Constant(decode_operand(operands)));
+            }
+            OpCode::Nil => self.push(Operand::Nil),
+            OpCode::True => self.push(Operand::Bool(true)),
+            OpCode::False => self.push(Operand::Bool(false)),
+            OpCode::Negate | OpCode::Not => {
+                let src = self.stack.pop()?;
+                let op = match op {
+                    OpCode::Negate => {
+                        // `__neg__` can run any code.
+                        self.settle();
+                        UnaryOp::Negate
+                    }
+                    _ => UnaryOp::Not,
+                };
+                let dst = self.stack.len();
+                self.emit(Instruction::Unary { op, dst, src });
+                self.push(Operand::Register(dst));
+            }
+            OpCode::Pop => {
+                self.stack.pop()?;
+            }
+            OpCode::GetLocal | OpCode::GetLocalLong => {
+                let local = self.local(decode_operand(operands))?;
+                self.push(local);
+            }
+            OpCode::GetLocal2 => {
+                let a = self.local(operands[0] as usize)?;
+                self.push(a);
+                let b = self.local(operands[1] as usize)?;
+                self.push(b);
+            }
+            OpCode::SetLocal | OpCode::SetLocalLong => {
+                let src = *self.stack.last()?;
+                self.assign(decode_operand(operands), src)?;
+            }
+            OpCode::AddLocalConst => {
+                let a = self.local(operands[0] as usize)?;
+                self.settle();
+                let dst = self.stack.len();
+                self.emit(Instruction::Binary {
+                    op: BinaryOp::Add,
+                    dst,
+                    a,
+                    b: Operand::Constant(operands[1] as usize),
+                });
+                self.push(Operand::Register(dst));
+            }
+            OpCode::IncrLocal => {
+                let slot = operands[0] as usize;
+                let a = self.local(slot)?;
+                self.settle();
+                self.emit(Instruction::Binary {
+                    op: BinaryOp::Add,
+                    dst: slot,
+                    a,
+                    b: Operand::Constant(operands[1] as usize),
+                });
+            }
+            OpCode::CloseUpValue => {
+                let register = self.stack.len().checked_sub(1)?;
+                self.materialize(register);
+                self.stack.pop();
+                self.emit(Instruction::CloseUpvalue { register });
+            }
+            OpCode::DefineGlobal | OpCode::DefineGlobalLong => {
+                let src = self.stack.pop()?;
+                let slot = decode_operand(operands);
+                self.emit(Instruction::DefineGlobal { slot, src });
+            }
+            OpCode::GetGlobal | OpCode::GetGlobalLong => {
+                let dst = self.stack.len();
+                let slot = decode_operand(operands);
+                self.emit(Instruction::GetGlobal { dst, slot });
+                self.push(Operand::Register(dst));
+            }
+            OpCode::SetGlobal | OpCode::SetGlobalLong => {
+                let src = *self.stack.last()?;
+                let slot = decode_operand(operands);
+                self.emit(Instruction::SetGlobal { slot, src });
+            }
+            OpCode::GetUpvalue | OpCode::GetUpvalueLong => {
+                let dst = self.stack.len();
+                let index = decode_operand(operands);
+                self.emit(Instruction::GetUpvalue { dst, index });
+                self.push(Operand::Register(dst));
+            }
+            OpCode::SetUpvalue | OpCode::SetUpvalueLong => {
+                let src = *self.stack.last()?;
+                let index = decode_operand(operands);
+                self.emit(Instruction::SetUpvalue { index, src });
+            }
+            // Getters and setters can run any code.
+            OpCode::GetProperty | OpCode::GetPropertyLong => {
+                let object = self.stack.pop()?;
+                self.settle();
+                let dst = self.stack.len();
+                self.emit(Instruction::GetProperty {
+                    dst,
+                    object,
+                    constant: decode_operand(operands),
+                });
+                self.push(Operand::Register(dst));
+            }
+            OpCode::SetProperty | OpCode::SetPropertyLong => {
+                let value = self.stack.pop()?;
+                let object = self.stack.pop()?;
+                self.settle();
+                let dst = self.stack.len();
+                self.emit(Instruction::SetProperty {
+                    dst,
+                    object,
+                    value,
+                    constant: decode_operand(operands),
+                });
+                self.push(Operand::Register(dst));
+            }
+            OpCode::Closure | OpCode::ClosureLong => {
+                let width = op.operand_width();
+                let constant = decode_operand(&operands[..width]);
+                let mut captures = vec![];
+                let mut at = width;
+                while at < operands.len() {
+                    let flags = operands[at];
+                    let width = if flags & UPVALUE_LONG != 0 { 3 } else { 1 };
+                    let index = decode_operand(&operands[at + 1..at + 1 + width]);
+                    let local = flags & UPVALUE_LOCAL != 0;
+                    // A captured local has to be in its slot.
+                    if local {
+                        self.local(index)?;
+                    }
+                    captures.push((local, index));
+                    at += 1 + width;
+                }
+
+                let dst = self.stack.len();
+                self.emit(Instruction::Closure {
+                    dst,
+                    constant,
+                    captures,
+                });
+                self.push(Operand::Register(dst));
+            }
+            OpCode::Jump | OpCode::Loop => {
+                self.materialize_all();
+                let target = self.target(op, offset, end)?;
+                self.jump(Instruction::Jump { target: 0 }, target, self.stack.len())?;
+                self.reachable = false;
+            }
+            OpCode::JumpIfFalse => {
+                self.materialize_all();
+                let cond = self.stack.len().checked_sub(1)?;
+                let target = self.target(op, offset, end)?;
+                self.jump(
+                    Instruction::JumpIfFalse { cond, target: 0 },
+                    target,
+                    cond + 1,
+                )?;
+            }
+            // Falls through when the local is less than the constant, and
+            // otherwise jumps with the comparison's result pushed.
+            OpCode::JumpIfLessLocal => {
+                let a = self.local(operands[0] as usize)?;
+                self.settle();
+                self.materialize_all();
+                let cond = self.stack.len();
+                self.register_count = self.register_count.max(cond + 1);
+                self.emit(Instruction::Binary {
+                    op: BinaryOp::Less,
+                    dst: cond,
+                    a,
+                    b: Operand::Constant(operands[1] as usize),
+                });
+                let target = self.target(op, offset, end)?;
+                self.jump(
+                    Instruction::JumpIfFalse { cond, target: 0 },
+                    target,
+                    cond + 1,
+                )?;
+            }
+            OpCode::Call | OpCode::TailCall => {
+                self.materialize_all();
+                let arg_count = operands[0] as usize;
+                let callee = self.stack.len().checked_sub(arg_count + 1)?;
+                self.emit(match op {
+                    OpCode::Call => Instruction::Call { callee, arg_count },
+                    _ => Instruction::TailCall { callee, arg_count },
+                });
+                self.stack.truncate(callee + 1);
+            }
+            OpCode::Print => {
+                self.materialize_all();
+                let count = operands[0] as usize;
+                let start = self.stack.len().checked_sub(count)?;
+                self.emit(Instruction::Print { start, count });
+                self.stack.truncate(start);
+            }
+            OpCode::Return => {
+                let src = self.stack.pop()?;
+                self.emit(Instruction::Return { src });
+                self.reachable = false;
+            }
+            _ => return None,
+        }
+
+        Some(())
+    }
+
+    fn emit(&mut self, instruction: Instruction) {
+        self.code.push(instruction);
+        self.offsets.push(self.end);
+    }
+
+    fn push(&mut self, operand: Operand) {
+        self.stack.push(operand);
+        self.register_count = self.register_count.max(self.stack.len());
+    }
+
+    /// Copies the value meant for stack slot `slot` into its register.
+    fn materialize(&mut self, slot: usize) {
+        let src = self.stack[slot];
+        if src != Operand::Register(slot) {
+            self.emit(Instruction::Move { dst: slot, src });
+            self.stack[slot] = Operand::Register(slot);
+        }
+    }
+
+    fn materialize_all(&mut self) {
+        for slot in 0..self.stack.len() {
+            self.materialize(slot);
+        }
+    }
+
+    /// Takes the copies of other registers still in flight before an
+    /// instruction that can run Lox code, which may assign to those
+    /// registers through an upvalue. Constants can stay where they are.
+    fn settle(&mut self) {
+        for slot in 0..self.stack.len() {
+            if let Operand::Register(register) = self.stack[slot]
+                && register != slot
+            {
+                self.materialize(slot);
+            }
+        }
+    }
+
+    /// Takes the copies of `register` still in flight before it is
+    /// written.
+    fn before_write(&mut self, register: usize) {
+        for slot in 0..self.stack.len() {
+            if slot != register && self.stack[slot] == Operand::Register(register) {
+                self.materialize(slot);
+            }
+        }
+    }
+
+    /// The local in `slot`, moved into its register first if it isn't
+    /// there yet.
+    fn local(&mut self, slot: usize) -> Option<Operand> {
+        if slot >= self.stack.len() {
+            return None;
+        }
+        self.materialize(slot);
+        Some(Operand::Register(slot))
+    }
+
+    fn assign(&mut self, slot: usize, src: Operand) -> Option<()> {
+        if slot >= self.stack.len() {
+            return None;
+        }
+        if src != Operand::Register(slot) {
+            self.before_write(slot);
+            self.emit(Instruction::Move { dst: slot, src });
+            self.stack[slot] = Operand::Register(slot);
+        }
+        Some(())
+    }
+
+    /// An assignment statement to a local. When the value was just
+    /// computed into the slot the `OP_POP` frees, the instruction that
+    /// computed it writes the local instead.
+    fn set_local_and_pop(&mut self, slot: usize) -> Option<()> {
+        let src = self.stack.pop()?;
+        let top = self.stack.len();
+        if slot >= top {
+            return None;
+        }
+
+        let in_flight = self
+            .stack
+            .iter()
+            .enumerate()
+            .any(|(at, operand)| at != slot && *operand == Operand::Register(slot));
+        if src == Operand::Register(top)
+            && !in_flight
+            && self.code.len() > self.block_start
+            && let Some(dst) = self.code.last_mut().and_then(Instruction::dst_mut)
+            && *dst == top
+        {
+            *dst = slot;
+            self.stack[slot] = Operand::Register(slot);
+            return Some(());
+        }
+
+        self.assign(slot, src)
+    }
+
+    /// Emits a jump to stack offset `target`, where the stack is `depth`
+    /// slots deep, all of them in their registers.
+    fn jump(&mut self, instruction: Instruction, target: usize, depth: usize) -> Option<()> {
+        match self.depths[target] {
+            Some(known) if known != depth => return None,
+            _ => self.depths[target] = Some(depth),
+        }
+
+        self.jumps.push((self.code.len(), target));
+        self.emit(instruction);
+        Some(())
+    }
+
+    /// Starts the code at a jump target. Whatever the stack held on the
+    /// way in is in its registers by now.
+    fn land(&mut self, offset: usize) -> Option<()> {
+        if self.reachable {
+            self.materialize_all();
+        }
+
+        let depth = match self.depths[offset] {
+            Some(depth) if self.reachable && depth != self.stack.len() => return None,
+            Some(depth) => depth,
+            None => self.stack.len(),
+        };
+        self.depths[offset] = Some(depth);
+        self.stack = (0..depth).map(Operand::Register).collect();
+        self.register_count = self.register_count.max(depth);
+
+        self.labels[offset] = Some(self.code.len());
+        self.block_start = self.code.len();
+        self.reachable = true;
+        Some(())
+    }
+}
@@ -0,0 +1,137 @@
+use std::ops::Range;
+
+use crate::heap::Heap;
+use crate::value::Value;
+
+/// What a stack slot holds: the `Value` itself, or with the `nan_boxing`
+/// feature the same value packed into eight bytes.
+#[cfg(not(feature = "nan_boxing"))]
+type Slot = Value;
+#[cfg(feature = "nan_boxing")]
+type Slot = crate::nanbox::NanBox;
+
+#[cfg(not(feature = "nan_boxing"))]
+#[inline]
+fn pack(value: Value) -> Slot {
+    value
+}
+
+#[cfg(not(feature = "nan_boxing"))]
+#[inline]
+fn unpack(slot: Slot) -> Value {
+    slot
+}
+
+#[cfg(feature = "nan_boxing")]
+#[inline]
+fn pack(value: Value) -> Slot {
+    Slot::from(value)
+}
+
+#[cfg(feature = "nan_boxing")]
+#[inline]
+fn unpack(slot: Slot) -> Value {
+    Value::from(slot)
+}
+
+/// The VM's value stack. Values go in and come out as `Value`s whichever
+/// representation the slots use, so the VM reads the same either way.
+#[derive(Debug)]
+pub struct Stack {
+    slots: Vec<Slot>,
+}
+
+impl Stack {
+    pub fn new() -> Self {
+        trace!("stack::Stack::new()");
+        Self { slots: vec![] }
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    #[inline]
+    pub fn push(&mut self, value: Value) {
+        self.slots.push(pack(value));
+    }
+
+    #[inline]
+    pub fn pop(&mut self) -> Option<Value> {
+        self.slots.pop().map(unpack)
+    }
+
+    /// The value `at` slots below the top, where zero is the top.
+    #[inline]
+    pub fn peek(&self, at: usize) -> Option<Value> {
+        let index = self.slots.len().checked_sub(at + 1)?;
+        self.get(index)
+    }
+
+    #[inline]
+    pub fn get(&self, index: usize) -> Option<Value> {
+        self.slots.get(index).cloned().map(unpack)
+    }
+
+    /// Whether the value `at` slots below the top is falsy, without
+    /// unpacking it.
+    #[inline]
+    pub fn is_falsy_at(&self, at: usize) -> Option<bool> {
+        let index = self.slots.len().checked_sub(at + 1)?;
+        Some(self.slots[index].is_falsy())
+    }
+
+    /// Pops the top two values and compares them, without unpacking them.
+    pub fn pop_equal(&mut self, heap: &Heap) -> Option<bool> {
+        let b = self.slots.pop()?;
+        let a = self.slots.pop()?;
+        Some(a.eq(&b, heap))
+    }
+
+    #[inline]
+    pub fn set(&mut self, index: usize, value: Value) {
+        self.slots[index] = pack(value);
+    }
+
+    pub fn swap(&mut self, a: usize, b: usize) {
+        self.slots.swap(a, b);
+    }
+
+    /// Pads the stack with nils up to `len` slots.
+    pub fn fill(&mut self, len: usize) {
+        if self.slots.len() < len {
+            self.slots.resize(len, pack(Value::Nil));
+        }
+    }
+
+    pub fn truncate(&mut self, len: usize) {
+        self.slots.truncate(len);
+    }
+
+    pub fn clear(&mut self) {
+        self.slots.clear();
+    }
+
+    /// Removes the slots in `range`, sliding the ones above it down.
+    pub fn remove(&mut self, range: Range<usize>) {
+        self.slots.drain(range);
+    }
+
+    pub fn split_off(&mut self, at: usize) -> Vec<Value> {
+        self.slots.split_off(at).into_iter().map(unpack).collect()
+    }
+
+    /// Copies of the values from `start` to the top.
+    pub fn values_from(&self, start: usize) -> Vec<Value> {
+        self.slots[start..].iter().cloned().map(unpack).collect()
+    }
+
+    pub fn extend(&mut self, values: impl IntoIterator<Item = Value>) {
+        self.slots.extend(values.into_iter().map(pack));
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Value> + '_ {
+        self.slots.iter().cloned().map(unpack)
+    }
+}
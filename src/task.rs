@@ -0,0 +1,76 @@
+use crate::{function::Upvalue, heap::Handle, value::Value, vm::CallFrame};
+
+/// A suspended async call: its frame, the stack window that belonged to it
+/// and the upvalues that pointed into that window, as offsets from its base.
+#[derive(Debug)]
+pub struct Task {
+    pub frame: CallFrame,
+    pub stack: Vec<Value>,
+    pub upvalues: Vec<(usize, Handle<Upvalue>)>,
+}
+
+impl Task {
+    pub fn new(
+        frame: CallFrame,
+        stack: Vec<Value>,
+        upvalues: Vec<(usize, Handle<Upvalue>)>,
+    ) -> Self {
+        Self {
+            frame,
+            stack,
+            upvalues,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum PromiseState {
+    Pending { waiters: Vec<Task> },
+    Resolved { value: Value },
+}
+
+/// Result of an async call or `sleep`, resolved at most once.
+#[derive(Debug)]
+pub struct Promise {
+    pub state: PromiseState,
+}
+
+impl Promise {
+    pub fn new() -> Self {
+        Self {
+            state: PromiseState::Pending { waiters: vec![] },
+        }
+    }
+
+    pub fn value(&self) -> Option<&Value> {
+        match &self.state {
+            PromiseState::Resolved { value } => Some(value),
+            PromiseState::Pending { waiters: _ } => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum TimerAction {
+    Resolve(Handle<Promise>),
+    Call(Value),
+}
+
+/// Work scheduled by `sleep` or `setTimeout`. Timers due at the same time
+/// fire in the order they were created.
+#[derive(Debug)]
+pub struct Timer {
+    pub due: f64,
+    pub sequence: usize,
+    pub action: TimerAction,
+}
+
+impl Timer {
+    pub fn new(due: f64, sequence: usize, action: TimerAction) -> Self {
+        Self {
+            due,
+            sequence,
+            action,
+        }
+    }
+}
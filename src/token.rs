@@ -1,5 +1,3 @@
-use log::trace;
-
 #[derive(Debug, PartialEq, Clone)]
 pub enum TokenType {
     Error,
@@ -11,8 +9,11 @@ pub enum TokenType {
     RightParen,
     LeftBrace,
     RightBrace,
+    LeftBracket,
+    RightBracket,
     Comma,
     Dot,
+    DotDotDot,
     Plus,
     Minus,
     Slash,
@@ -26,27 +27,38 @@ pub enum TokenType {
     GreaterEqual,
     Less,
     LessEqual,
+    QuestionDot,
+    QuestionBracket,
     // Literal
     Identifier,
     String,
     Number,
     // Keyword
     And,
+    Assert,
+    Async,
+    Await,
+    Case,
     Class,
+    Defer,
     Else,
+    Enum,
     False,
     For,
     Fun,
     If,
+    Is,
     Nil,
     Or,
     Print,
     Return,
     Super,
     This,
+    Trait,
     True,
     Var,
     While,
+    With,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
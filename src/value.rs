@@ -1,15 +1,39 @@
 use std::{fmt, rc::Rc};
 
+use crate::class::{BoundMethod, Class, Instance, Trait};
+use crate::enums::{Enum, Variant, VariantConstructor};
 use crate::function::{Closure, Function, Upvalue};
+use crate::heap::{Handle, Heap};
+use crate::interner::InternedString;
+use crate::task::Promise;
+use crate::vm::VM;
+
+/// Why a native function failed.
+#[derive(Debug)]
+pub enum NativeError {
+    /// Reported as a runtime error at the call site.
+    Message(String),
+    /// Lox code the native ran, such as a `toString` method, already
+    /// reported a runtime error.
+    Reported,
+}
+
+impl From<String> for NativeError {
+    fn from(message: String) -> Self {
+        Self::Message(message)
+    }
+}
+
+pub type NativeResult = Result<Value, NativeError>;
 
 #[derive(Debug, Clone)]
 pub struct NativeFn {
     pub name: String,
-    pub function: fn(usize, Vec<Value>) -> Value,
+    pub function: fn(&mut VM, usize, Vec<Value>) -> NativeResult,
 }
 
 impl NativeFn {
-    pub fn new(name: &str, function: fn(usize, Vec<Value>) -> Value) -> Self {
+    pub fn new(name: &str, function: fn(&mut VM, usize, Vec<Value>) -> NativeResult) -> Self {
         Self {
             name: String::from(name),
             function,
@@ -17,16 +41,62 @@ impl NativeFn {
     }
 }
 
+type ListHandle = Handle<Vec<Value>>;
+
 #[derive(Debug, Clone)]
 pub enum Value {
-    Bool { value: bool },
-    Number { value: f64 },
+    Bool {
+        value: bool,
+    },
+    Number {
+        value: f64,
+    },
     Nil,
-    String { value: Rc<String> },
-    Closure { value: Closure },
-    Upvalue { value: Upvalue },
-    Function { value: Function },
-    NativeFn { value: NativeFn },
+    String {
+        value: InternedString,
+    },
+    Closure {
+        value: Handle<Closure>,
+    },
+    Upvalue {
+        value: Handle<Upvalue>,
+    },
+    Function {
+        value: Rc<Function>,
+    },
+    NativeFn {
+        value: NativeFn,
+    },
+    Enum {
+        value: Rc<Enum>,
+    },
+    VariantConstructor {
+        value: VariantConstructor,
+    },
+    Variant {
+        value: Rc<Variant>,
+    },
+    Class {
+        value: Handle<Class>,
+    },
+    Instance {
+        value: Handle<Instance>,
+    },
+    BoundMethod {
+        value: Handle<BoundMethod>,
+    },
+    Trait {
+        value: Handle<Trait>,
+    },
+    List {
+        value: Handle<Vec<Value>>,
+    },
+    Promise {
+        value: Handle<Promise>,
+    },
+    /// Fills global slots the compiler has handed out but whose declaration
+    /// hasn't run yet. Never reaches the stack.
+    Undefined,
 }
 
 impl Value {
@@ -40,20 +110,158 @@ impl Value {
             Self::Upvalue { value: _ } => false,
             Self::Function { value: _ } => false,
             Self::NativeFn { value: _ } => false,
+            Self::Enum { value: _ } => false,
+            Self::VariantConstructor { value: _ } => false,
+            Self::Variant { value: _ } => false,
+            Self::Class { value: _ } => false,
+            Self::Instance { value: _ } => false,
+            Self::BoundMethod { value: _ } => false,
+            Self::Trait { value: _ } => false,
+            Self::List { value: _ } => false,
+            Self::Promise { value: _ } => false,
+            Self::Undefined => true,
         }
     }
 
-    pub fn eq(&self, other: Value) -> bool {
+    /// Objects compare by identity, except lists, which compare their items
+    /// and so need the heap they live in.
+    pub fn eq(&self, other: &Value, heap: &Heap) -> bool {
+        self.eq_in(other, heap, &mut vec![])
+    }
+
+    /// `comparing` holds the pairs of lists already being compared further
+    /// up, so a list that contains itself compares equal instead of
+    /// recursing forever.
+    fn eq_in(
+        &self,
+        other: &Value,
+        heap: &Heap,
+        comparing: &mut Vec<(ListHandle, ListHandle)>,
+    ) -> bool {
         match (self, other) {
-            (Self::Bool { value: a }, Self::Bool { value: b }) => *a == b,
-            (Self::Number { value: a }, Self::Number { value: b }) => *a == b,
+            (Self::Bool { value: a }, Self::Bool { value: b }) => a == b,
+            (Self::Number { value: a }, Self::Number { value: b }) => a == b,
             (Self::Nil, Self::Nil) => true,
-            (Self::String { value: a }, Self::String { value: b }) => *a == b,
+            (Self::String { value: a }, Self::String { value: b }) => a == b,
+            (Self::Enum { value: a }, Self::Enum { value: b }) => Rc::ptr_eq(a, b),
+            (Self::Variant { value: a }, Self::Variant { value: b }) => {
+                a.is_same_variant(&b.enum_def, b.variant)
+                    && a.payload
+                        .iter()
+                        .zip(b.payload.iter())
+                        .all(|(a, b)| a.eq_in(b, heap, comparing))
+            }
+            (Self::Class { value: a }, Self::Class { value: b }) => a == b,
+            (Self::Instance { value: a }, Self::Instance { value: b }) => a == b,
+            (Self::Trait { value: a }, Self::Trait { value: b }) => a == b,
+            (Self::Promise { value: a }, Self::Promise { value: b }) => a == b,
+            (Self::List { value: a }, Self::List { value: b }) => {
+                if a == b || comparing.contains(&(*a, *b)) {
+                    return true;
+                }
+
+                comparing.push((*a, *b));
+                let (a, b) = (heap.get(*a), heap.get(*b));
+                let equal = a.len() == b.len()
+                    && a.iter()
+                        .zip(b.iter())
+                        .all(|(a, b)| a.eq_in(b, heap, comparing));
+                comparing.pop();
+
+                equal
+            }
             _ => false,
         }
     }
+
+    /// Formats the value the way `print` shows it, looking objects up in
+    /// `heap`.
+    pub fn display<'a>(&'a self, heap: &'a Heap) -> Display<'a> {
+        Display { value: self, heap }
+    }
+}
+
+/// A value together with the heap its objects live in.
+pub struct Display<'a> {
+    value: &'a Value,
+    heap: &'a Heap,
+}
+
+impl fmt::Display for Display<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_value(f, self.value, self.heap, &mut vec![])
+    }
 }
 
+/// `printing` holds the lists being printed further up; a list met again
+/// inside itself is shown as `[...]`.
+fn write_value(
+    f: &mut fmt::Formatter<'_>,
+    value: &Value,
+    heap: &Heap,
+    printing: &mut Vec<ListHandle>,
+) -> fmt::Result {
+    match value {
+        Value::Closure { value } => write_function_name(f, &heap.get(*value).function),
+        Value::Variant { value } => {
+            write!(f, "{}.{}", value.enum_def.name, value.name())?;
+
+            if !value.payload.is_empty() {
+                write!(f, "(")?;
+                for (i, field) in value.payload.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write_value(f, field, heap, printing)?;
+                }
+                write!(f, ")")?;
+            }
+
+            Ok(())
+        }
+        Value::Class { value } => write!(f, "<class {}>", heap.get(*value).name),
+        Value::Instance { value } => {
+            let class = heap.get(heap.get(*value).class);
+            write!(f, "<{} instance>", class.name)
+        }
+        Value::BoundMethod { value } => {
+            let method = heap.get(heap.get(*value).method);
+            write_function_name(f, &method.function)
+        }
+        Value::Trait { value } => write!(f, "<trait {}>", heap.get(*value).name),
+        Value::Promise { value } => match heap.get(*value).value() {
+            Some(_) => write!(f, "<promise resolved>"),
+            None => write!(f, "<promise pending>"),
+        },
+        Value::List { value } => {
+            if printing.contains(value) {
+                return write!(f, "[...]");
+            }
+
+            printing.push(*value);
+            write!(f, "[")?;
+            for (i, item) in heap.get(*value).iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+                write_value(f, item, heap, printing)?;
+            }
+            printing.pop();
+            write!(f, "]")
+        }
+        value => write!(f, "{value}"),
+    }
+}
+
+fn write_function_name(f: &mut fmt::Formatter<'_>, function: &Function) -> fmt::Result {
+    match &function.name {
+        Some(name) => write!(f, "<fn {name}>"),
+        None => write!(f, "<script>"),
+    }
+}
+
+/// Shows objects without looking into the heap; `Value::display` gives the
+/// full form.
 impl fmt::Display for Value {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -61,26 +269,25 @@ impl fmt::Display for Value {
             Self::Number { value } => write!(f, "{value}"),
             Self::Nil => write!(f, "nil"),
             Self::String { value } => write!(f, "{value}"),
-            Self::Closure { value } => {
-                let name = &value.function.name;
-
-                match name {
-                    Some(name) => write!(f, "<fn {name}>"),
-                    None => write!(f, "<script>"),
-                }
-            }
+            Self::Closure { value } => write!(f, "<closure {value:?}>"),
             Self::Upvalue { value: _ } => write!(f, "upvalue"),
-            Self::Function { value } => {
-                let name = &value.name;
-
-                match name {
-                    Some(name) => write!(f, "<fn {name}>"),
-                    None => write!(f, "<script>"),
-                }
-            }
+            Self::Function { value } => write_function_name(f, value),
             Self::NativeFn { value } => {
                 write!(f, "<native fn {}>", value.name)
             }
+            Self::Enum { value } => write!(f, "<enum {}>", value.name),
+            Self::VariantConstructor { value } => {
+                let variant = &value.enum_def.variants[value.variant];
+                write!(f, "<variant {}.{}>", value.enum_def.name, variant.name)
+            }
+            Self::Variant { value } => write!(f, "{}.{}", value.enum_def.name, value.name()),
+            Self::Class { value } => write!(f, "<class {value:?}>"),
+            Self::Instance { value } => write!(f, "<instance {value:?}>"),
+            Self::BoundMethod { value } => write!(f, "<bound method {value:?}>"),
+            Self::Trait { value } => write!(f, "<trait {value:?}>"),
+            Self::Promise { value } => write!(f, "<promise {value:?}>"),
+            Self::List { value } => write!(f, "<list {value:?}>"),
+            Self::Undefined => write!(f, "undefined"),
         }
     }
 }
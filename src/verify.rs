@@ -0,0 +1,135 @@
+use crate::function::Function;
+use crate::value::Value;
+use crate::vm::{Chunk, OpCode, UPVALUE_LONG, decode_operand};
+
+/// Checks compiled code once before it runs, so the dispatch loop can
+/// read it without checking as it goes. Every opcode must be known, every
+/// operand present, every constant index and global slot in range, every
+/// jump must land on an instruction, and the code must end in an
+/// instruction that doesn't fall through, so the cursor never runs off the
+/// end. Functions in the constants are checked the same way.
+pub fn verify(function: &Function, global_count: usize) -> Result<(), String> {
+    trace!("verify::verify(function: {:?})", function.name);
+    let name = function.name.as_deref().unwrap_or("script");
+    verify_chunk(&function.chunk, global_count)
+        .map_err(|message| format!("{message} in {name}"))?;
+
+    for constant in function.chunk.constants.iter() {
+        if let Value::Function { value } = constant {
+            verify(value, global_count)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Where in an instruction's operands a constant index is, and how wide.
+fn constant_operand(op: OpCode) -> Option<(usize, usize)> {
+    match op {
+        OpCode::Constant
+        | OpCode::Closure
+        | OpCode::GetProperty
+        | OpCode::SetProperty
+        | OpCode::Class
+        | OpCode::Method
+        | OpCode::Getter
+        | OpCode::Setter
+        | OpCode::StaticMethod
+        | OpCode::StaticField
+        | OpCode::Trait
+        | OpCode::Assert => Some((0, 1)),
+        OpCode::ConstantLong
+        | OpCode::ClosureLong
+        | OpCode::GetPropertyLong
+        | OpCode::SetPropertyLong
+        | OpCode::ClassLong
+        | OpCode::MethodLong
+        | OpCode::GetterLong
+        | OpCode::SetterLong
+        | OpCode::StaticMethodLong
+        | OpCode::StaticFieldLong
+        | OpCode::TraitLong
+        | OpCode::AssertLong => Some((0, 3)),
+        OpCode::AddLocalConst | OpCode::IncrLocal | OpCode::JumpIfLessLocal => Some((1, 1)),
+        _ => None,
+    }
+}
+
+fn global_operand(op: OpCode) -> Option<usize> {
+    match op {
+        OpCode::DefineGlobal | OpCode::GetGlobal | OpCode::SetGlobal => Some(1),
+        OpCode::DefineGlobalLong | OpCode::GetGlobalLong | OpCode::SetGlobalLong => Some(3),
+        _ => None,
+    }
+}
+
+fn verify_chunk(chunk: &Chunk, global_count: usize) -> Result<(), String> {
+    let code = &chunk.instructions;
+    let mut starts = vec![false; code.len()];
+    let mut targets = vec![];
+    let mut last = None;
+
+    let mut offset = 0;
+    while offset < code.len() {
+        let op = OpCode::from_u8(code[offset]);
+        if op == OpCode::Unknown {
+            return Err(format!("unknown opcode {} at {offset}", code[offset]));
+        }
+
+        let end = offset + 1 + op.operand_width();
+        let Some(operands) = code.get(offset + 1..end) else {
+            return Err(format!("missing operands at {offset}"));
+        };
+
+        if let Some((at, width)) = constant_operand(op) {
+            let constant = decode_operand(&operands[at..at + width]);
+            if constant >= chunk.constants.len() {
+                return Err(format!("constant {constant} out of range at {offset}"));
+            }
+        }
+
+        if let Some(width) = global_operand(op)
+            && decode_operand(&operands[..width]) >= global_count
+        {
+            return Err(format!("global slot out of range at {offset}"));
+        }
+
+        if op.is_jump() {
+            match op.jump_target(end, operands) {
+                Some(target) => targets.push((offset, target)),
+                None => return Err(format!("loop before the start at {offset}")),
+            }
+        }
+
+        let mut end = end;
+        if let OpCode::Closure | OpCode::ClosureLong = op {
+            let Value::Function { value } = &chunk.constants[decode_operand(operands)] else {
+                return Err(format!("closure of a non-function at {offset}"));
+            };
+            for _ in 0..value.upvalue_count {
+                let Some(flags) = code.get(end) else {
+                    return Err(format!("missing upvalue at {offset}"));
+                };
+                end += if flags & UPVALUE_LONG != 0 { 4 } else { 2 };
+            }
+            if end > code.len() {
+                return Err(format!("missing upvalue at {offset}"));
+            }
+        }
+
+        starts[offset] = true;
+        last = Some(op);
+        offset = end;
+    }
+
+    for (offset, target) in targets {
+        if !starts.get(target).is_some_and(|start| *start) {
+            return Err(format!("jump to {target} at {offset}"));
+        }
+    }
+
+    match last {
+        Some(OpCode::Return | OpCode::Jump | OpCode::Loop) => Ok(()),
+        _ => Err(String::from("code falls off the end")),
+    }
+}
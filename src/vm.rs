@@ -1,14 +1,25 @@
-use log::trace;
-use std::collections::HashMap;
-use std::ops::Deref;
+use std::collections::VecDeque;
+use std::io::{self, Write};
 use std::rc::Rc;
+use std::thread;
+use std::time::{Duration, Instant};
 
 use lox_rust_2::{binary_bool_op, binary_number_op};
 
-use crate::args::Args;
+use crate::args::{Args, Engine};
+use crate::class::{BoundMethod, Class, Instance, Trait};
 use crate::compile::compile;
+use crate::enums::{Variant, VariantConstructor};
+use crate::format;
 use crate::function::{Closure, Function, Upvalue, UpvalueLoc};
-use crate::value::{NativeFn, Value};
+use crate::globals::Globals;
+use crate::heap::{Handle, Heap, Marker};
+use crate::interner::{InternedString, Interner};
+use crate::register::{self, BinaryOp, Instruction, Operand, UnaryOp};
+use crate::stack::Stack;
+use crate::task::{Promise, PromiseState, Task, Timer, TimerAction};
+use crate::value::{NativeError, NativeFn, NativeResult, Value};
+use crate::verify::verify;
 
 static MAX_FRAMES: usize = 255;
 
@@ -20,8 +31,38 @@ macro_rules! try_or_return {
     };
 }
 
-#[derive(Debug)]
-#[repr(usize)]
+/// Largest operand of a `*_LONG` instruction, which is three bytes wide.
+pub const MAX_LONG_OPERAND: usize = 0xff_ffff;
+
+/// The value of a big-endian operand.
+#[inline]
+pub fn decode_operand(bytes: &[u8]) -> usize {
+    bytes
+        .iter()
+        .fold(0, |operand, byte| operand << 8 | *byte as usize)
+}
+
+/// Longest delay `sleep` and `setTimeout` accept, in milliseconds. It is
+/// JavaScript's limit for `setTimeout`, about 24.8 days.
+const MAX_DELAY: f64 = 2_147_483_647.0;
+
+/// Checks a delay passed to the timer native `name`.
+fn timer_delay(name: &str, delay: f64) -> Result<f64, String> {
+    if delay.is_finite() && delay <= MAX_DELAY {
+        Ok(delay)
+    } else {
+        Err(format!(
+            "{name}() expects a finite delay of at most {MAX_DELAY} milliseconds."
+        ))
+    }
+}
+
+/// Bits of the byte that precedes each upvalue index after `OP_CLOSURE`.
+pub const UPVALUE_LOCAL: u8 = 1;
+pub const UPVALUE_LONG: u8 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[repr(u8)]
 pub enum OpCode {
     Return,
     Constant,
@@ -52,13 +93,65 @@ pub enum OpCode {
     GetUpvalue,
     SetUpvalue,
     CloseUpValue,
+    GetProperty,
+    MatchVariant,
+    Destructure,
+    Class,
+    Method,
+    SetProperty,
+    GetIndex,
+    SetIndex,
+    Getter,
+    Setter,
+    StaticMethod,
+    StaticField,
+    Trait,
+    Implement,
+    Is,
+    JumpIfNil,
+    TailCall,
+    Defer,
+    BuildList,
+    ExtendList,
+    Spread,
+    CallSpread,
+    Assert,
+    PrintWith,
+    Await,
+    ConstantLong,
+    DefineGlobalLong,
+    GetGlobalLong,
+    SetGlobalLong,
+    GetLocalLong,
+    SetLocalLong,
+    ClosureLong,
+    GetUpvalueLong,
+    SetUpvalueLong,
+    GetPropertyLong,
+    SetPropertyLong,
+    ClassLong,
+    MethodLong,
+    GetterLong,
+    SetterLong,
+    StaticMethodLong,
+    StaticFieldLong,
+    TraitLong,
+    AssertLong,
+    NotEqual,
+    GreaterEqual,
+    LessEqual,
+    AddLocalConst,
+    IncrLocal,
+    GetLocal2,
+    JumpIfLessLocal,
+    TailCallSpread,
     Unknown,
 }
 
 impl OpCode {
-    pub fn from_usize(num: usize) -> Self {
-        trace!("vm::OpCode::from_usize(num: {num})");
-        match num {
+    pub fn from_u8(byte: u8) -> Self {
+        trace!("vm::OpCode::from_u8(byte: {byte})");
+        match byte {
             0 => Self::Return,
             1 => Self::Constant,
             2 => Self::Negate,
@@ -87,10 +180,156 @@ impl OpCode {
             25 => Self::Closure,
             26 => Self::GetUpvalue,
             27 => Self::SetUpvalue,
-            29 => Self::CloseUpValue,
+            28 => Self::CloseUpValue,
+            29 => Self::GetProperty,
+            30 => Self::MatchVariant,
+            31 => Self::Destructure,
+            32 => Self::Class,
+            33 => Self::Method,
+            34 => Self::SetProperty,
+            35 => Self::GetIndex,
+            36 => Self::SetIndex,
+            37 => Self::Getter,
+            38 => Self::Setter,
+            39 => Self::StaticMethod,
+            40 => Self::StaticField,
+            41 => Self::Trait,
+            42 => Self::Implement,
+            43 => Self::Is,
+            44 => Self::JumpIfNil,
+            45 => Self::TailCall,
+            46 => Self::Defer,
+            47 => Self::BuildList,
+            48 => Self::ExtendList,
+            49 => Self::Spread,
+            50 => Self::CallSpread,
+            51 => Self::Assert,
+            52 => Self::PrintWith,
+            53 => Self::Await,
+            54 => Self::ConstantLong,
+            55 => Self::DefineGlobalLong,
+            56 => Self::GetGlobalLong,
+            57 => Self::SetGlobalLong,
+            58 => Self::GetLocalLong,
+            59 => Self::SetLocalLong,
+            60 => Self::ClosureLong,
+            61 => Self::GetUpvalueLong,
+            62 => Self::SetUpvalueLong,
+            63 => Self::GetPropertyLong,
+            64 => Self::SetPropertyLong,
+            65 => Self::ClassLong,
+            66 => Self::MethodLong,
+            67 => Self::GetterLong,
+            68 => Self::SetterLong,
+            69 => Self::StaticMethodLong,
+            70 => Self::StaticFieldLong,
+            71 => Self::TraitLong,
+            72 => Self::AssertLong,
+            73 => Self::NotEqual,
+            74 => Self::GreaterEqual,
+            75 => Self::LessEqual,
+            76 => Self::AddLocalConst,
+            77 => Self::IncrLocal,
+            78 => Self::GetLocal2,
+            79 => Self::JumpIfLessLocal,
+            80 => Self::TailCallSpread,
             _ => Self::Unknown,
         }
     }
+
+    /// The variant taking a three byte operand, for opcodes whose operand
+    /// is a constant index or a slot and can outgrow a byte.
+    pub fn long(self) -> Option<Self> {
+        trace!("vm::OpCode::long()");
+        match self {
+            Self::Constant => Some(Self::ConstantLong),
+            Self::DefineGlobal => Some(Self::DefineGlobalLong),
+            Self::GetGlobal => Some(Self::GetGlobalLong),
+            Self::SetGlobal => Some(Self::SetGlobalLong),
+            Self::GetLocal => Some(Self::GetLocalLong),
+            Self::SetLocal => Some(Self::SetLocalLong),
+            Self::Closure => Some(Self::ClosureLong),
+            Self::GetUpvalue => Some(Self::GetUpvalueLong),
+            Self::SetUpvalue => Some(Self::SetUpvalueLong),
+            Self::GetProperty => Some(Self::GetPropertyLong),
+            Self::SetProperty => Some(Self::SetPropertyLong),
+            Self::Class => Some(Self::ClassLong),
+            Self::Method => Some(Self::MethodLong),
+            Self::Getter => Some(Self::GetterLong),
+            Self::Setter => Some(Self::SetterLong),
+            Self::StaticMethod => Some(Self::StaticMethodLong),
+            Self::StaticField => Some(Self::StaticFieldLong),
+            Self::Trait => Some(Self::TraitLong),
+            Self::Assert => Some(Self::AssertLong),
+            _ => None,
+        }
+    }
+
+    /// How many operand bytes follow the opcode. `OP_CLOSURE` is followed
+    /// by its upvalue descriptors too, which `Chunk::instruction_len`
+    /// counts.
+    pub fn operand_width(self) -> usize {
+        match self {
+            OpCode::Constant
+            | OpCode::Print
+            | OpCode::PrintWith
+            | OpCode::DefineGlobal
+            | OpCode::GetGlobal
+            | OpCode::SetGlobal
+            | OpCode::GetLocal
+            | OpCode::SetLocal
+            | OpCode::Call
+            | OpCode::TailCall
+            | OpCode::Closure
+            | OpCode::GetUpvalue
+            | OpCode::SetUpvalue
+            | OpCode::GetProperty
+            | OpCode::SetProperty
+            | OpCode::MatchVariant
+            | OpCode::Class
+            | OpCode::Method
+            | OpCode::Getter
+            | OpCode::Setter
+            | OpCode::StaticMethod
+            | OpCode::StaticField
+            | OpCode::Trait
+            | OpCode::BuildList
+            | OpCode::ExtendList
+            | OpCode::Assert => 1,
+            OpCode::Jump | OpCode::JumpIfFalse | OpCode::JumpIfNil | OpCode::Loop => 2,
+            OpCode::AddLocalConst | OpCode::IncrLocal | OpCode::GetLocal2 => 2,
+            OpCode::JumpIfLessLocal => 4,
+            op if op.is_long() => 3,
+            _ => 0,
+        }
+    }
+
+    pub fn is_long(self) -> bool {
+        (Self::ConstantLong as u8..=Self::AssertLong as u8).contains(&(self as u8))
+    }
+
+    /// Whether the instruction jumps. Its offset is the last two bytes of
+    /// its operands, counted from the end of the instruction.
+    pub fn is_jump(self) -> bool {
+        matches!(
+            self,
+            OpCode::Jump
+                | OpCode::JumpIfFalse
+                | OpCode::JumpIfNil
+                | OpCode::Loop
+                | OpCode::JumpIfLessLocal
+        )
+    }
+
+    /// Where a jump ending at `end` with `operands` lands, or `None` for a
+    /// loop reaching back past the start of the code.
+    pub fn jump_target(self, end: usize, operands: &[u8]) -> Option<usize> {
+        let offset = decode_operand(&operands[operands.len().checked_sub(2)?..]);
+        match self {
+            OpCode::Loop => end.checked_sub(offset),
+            _ => Some(end + offset),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -106,11 +345,67 @@ impl Loc {
     }
 }
 
+/// A per-byte value stored as runs: each entry is the offset a run starts
+/// at and the value every byte up to the next run shares. Lines and columns
+/// change far less often than bytes are written.
+#[derive(Debug, Clone)]
+pub struct Runs {
+    runs: Vec<(usize, usize)>,
+}
+
+impl Runs {
+    fn new() -> Self {
+        Self { runs: vec![] }
+    }
+
+    fn push(&mut self, offset: usize, value: usize) {
+        if self.runs.last().is_some_and(|(_, last)| *last == value) {
+            return;
+        }
+        self.runs.push((offset, value));
+    }
+
+    fn get(&self, offset: usize) -> usize {
+        let run = self.runs.partition_point(|(start, _)| *start <= offset);
+        run.checked_sub(1).map_or(0, |run| self.runs[run].1)
+    }
+
+    fn truncate(&mut self, len: usize) {
+        let keep = self.runs.partition_point(|(start, _)| *start < len);
+        self.runs.truncate(keep);
+    }
+
+    /// Splits off the runs from `at` on, rebased to start at zero. The run
+    /// covering `at` continues into the returned half.
+    fn split_off(&mut self, at: usize) -> Runs {
+        let keep = self.runs.partition_point(|(start, _)| *start < at);
+        let mut runs = self.runs.split_off(keep);
+        if runs.first().is_none_or(|(start, _)| *start > at)
+            && let Some(&(_, value)) = self.runs.last()
+        {
+            runs.insert(0, (at, value));
+        }
+
+        for run in runs.iter_mut() {
+            run.0 -= at;
+        }
+        Runs { runs }
+    }
+
+    /// Appends runs split off another chunk, with `at` as their new start.
+    fn append(&mut self, other: Runs, at: usize) {
+        for (start, value) in other.runs {
+            self.push(start + at, value);
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Chunk {
-    pub instructions: Vec<usize>,
+    pub instructions: Vec<u8>,
     pub constants: Vec<Value>,
-    pub loc: Vec<Loc>,
+    lines: Runs,
+    cols: Runs,
 }
 
 impl Chunk {
@@ -119,14 +414,22 @@ impl Chunk {
         Self {
             instructions: vec![],
             constants: vec![],
-            loc: vec![],
+            lines: Runs::new(),
+            cols: Runs::new(),
         }
     }
 
-    pub fn write(&mut self, op: usize, line: usize, col: usize) {
-        trace!("vm::Chunk::write(op: {op}, line: {line}, col: {col})");
-        self.instructions.push(op);
-        self.loc.push(Loc::new(line, col));
+    pub fn write(&mut self, byte: u8, line: usize, col: usize) {
+        trace!("vm::Chunk::write(byte: {byte}, line: {line}, col: {col})");
+        let offset = self.instructions.len();
+        self.instructions.push(byte);
+        self.lines.push(offset, line);
+        self.cols.push(offset, col);
+    }
+
+    /// Where in the source the byte at `offset` was compiled from.
+    pub fn loc(&self, offset: usize) -> Loc {
+        Loc::new(self.lines.get(offset), self.cols.get(offset))
     }
 
     pub fn add_constant(&mut self, value: Value) -> usize {
@@ -135,36 +438,133 @@ impl Chunk {
         self.constants.len() - 1
     }
 
+    /// Drops everything written after `len`; constants are left in place.
+    pub fn truncate(&mut self, len: usize) {
+        trace!("vm::Chunk::truncate(len: {len})");
+        self.instructions.truncate(len);
+        self.lines.truncate(len);
+        self.cols.truncate(len);
+    }
+
+    /// Moves the code written after `at` into a chunk of its own, so the
+    /// parser can emit it again later with `append`. Jumps are relative and
+    /// constants stay put, so the moved code keeps working.
+    pub fn split_off(&mut self, at: usize) -> Chunk {
+        trace!("vm::Chunk::split_off(at: {at})");
+        Chunk {
+            instructions: self.instructions.split_off(at),
+            constants: vec![],
+            lines: self.lines.split_off(at),
+            cols: self.cols.split_off(at),
+        }
+    }
+
+    pub fn append(&mut self, mut other: Chunk) {
+        trace!("vm::Chunk::append(other)");
+        let at = self.instructions.len();
+        self.instructions.append(&mut other.instructions);
+        self.lines.append(other.lines, at);
+        self.cols.append(other.cols, at);
+    }
+
     pub fn clear(&mut self) {
         trace!("vm::Chunk::clear()");
         self.instructions.clear();
         self.constants.clear();
-        self.loc.clear();
+        self.lines.truncate(0);
+        self.cols.truncate(0);
     }
 
     pub fn len(&self) -> usize {
         trace!("vm::Chunk::len()");
         self.instructions.len()
     }
+
+    /// How many bytes the instruction at `offset` takes, operands included.
+    pub fn instruction_len(&self, offset: usize) -> usize {
+        trace!("vm::Chunk::instruction_len(offset: {offset})");
+        let op = OpCode::from_u8(self.instructions[offset]);
+        let mut len = 1 + op.operand_width();
+        if let OpCode::Closure | OpCode::ClosureLong = op {
+            let constant = decode_operand(&self.instructions[offset + 1..offset + len]);
+            let upvalue_count = match &self.constants[constant] {
+                Value::Function { value } => value.upvalue_count,
+                _ => 0,
+            };
+            for _ in 0..upvalue_count {
+                let flags = self.instructions[offset + len];
+                len += if flags & UPVALUE_LONG != 0 { 4 } else { 2 };
+            }
+        }
+
+        len
+    }
 }
 
 #[derive(Debug)]
 pub struct CallFrame {
-    closure: Closure,
+    closure: Handle<Closure>,
+    /// The closure's function, kept here so fetching an instruction
+    /// doesn't go through the heap.
+    function: Rc<Function>,
     cursor: usize,
     slot_base: usize,
+    deferred: Vec<Handle<Closure>>,
+    /// Set for async calls; resolved with the frame's return value.
+    promise: Option<Handle<Promise>>,
+    /// The register engine runs the frame, and `cursor` counts the
+    /// function's register instructions rather than bytes.
+    on_registers: bool,
 }
 
 impl CallFrame {
-    pub fn new(closure: Closure, cursor: usize, slot_base: usize) -> Self {
+    pub fn new(
+        closure: Handle<Closure>,
+        function: Rc<Function>,
+        cursor: usize,
+        slot_base: usize,
+    ) -> Self {
         Self {
             closure,
+            function,
             cursor,
             slot_base,
+            deferred: vec![],
+            promise: None,
+            on_registers: false,
+        }
+    }
+
+    /// Where the frame is in its bytecode, which is where errors are
+    /// reported. A frame on the register engine maps its cursor back
+    /// through the instruction it last fetched.
+    fn offset(&self) -> usize {
+        match &self.function.register_code {
+            Some(code) if self.on_registers => code.offsets[self.cursor - 1],
+            _ => self.cursor,
+        }
+    }
+
+    pub fn trace(&self, marker: &mut Marker) {
+        marker.handle(self.closure);
+        for closure in self.deferred.iter() {
+            marker.handle(*closure);
+        }
+        if let Some(promise) = self.promise {
+            marker.handle(promise);
         }
     }
 }
 
+/// Which table of a class an `OP_METHOD`-style instruction fills.
+#[derive(Debug)]
+enum MethodKind {
+    Method,
+    Getter,
+    Setter,
+    Static,
+}
+
 #[derive(Debug)]
 pub enum InterpretResult {
     Ok,
@@ -173,12 +573,20 @@ pub enum InterpretResult {
 }
 
 pub struct VM {
-    stack: Vec<Value>,
+    heap: Heap,
+    stack: Stack,
     source: String,
-    globals: HashMap<Rc<String>, Value>,
+    interner: Interner,
+    globals: Globals,
     frames: Vec<CallFrame>,
-    upvalues: Vec<Upvalue>,
-    open_upvalues: Vec<(usize, usize)>, // (stack_index, up_index)
+    /// Upvalues still pointing into the stack, sorted by stack slot.
+    open_upvalues: Vec<(usize, Handle<Upvalue>)>,
+    args: Args,
+    ready: VecDeque<(Task, Value)>,
+    timers: Vec<Timer>,
+    timer_sequence: usize,
+    started: Instant,
+    virtual_time: f64,
 }
 
 impl VM {
@@ -186,12 +594,19 @@ impl VM {
         trace!("vm::VM::new(args: {:?})", args);
 
         Self {
-            stack: vec![],
+            heap: Heap::new(),
+            stack: Stack::new(),
             source: String::new(),
-            globals: HashMap::new(),
+            interner: Interner::new(),
+            globals: Globals::new(),
             frames: vec![],
-            upvalues: vec![],
             open_upvalues: vec![],
+            args: args.clone(),
+            ready: VecDeque::new(),
+            timers: vec![],
+            timer_sequence: 0,
+            started: Instant::now(),
+            virtual_time: 0.0,
         }
     }
 
@@ -200,6 +615,7 @@ impl VM {
         self.frames.clear();
         self.stack.clear();
         self.globals.clear();
+        self.heap.clear();
     }
 
     pub fn interpret(&mut self, source: String) -> InterpretResult {
@@ -209,125 +625,548 @@ impl VM {
 
         self.source = source;
 
-        let Ok(function) = compile(self.source.as_str()) else {
+        let Ok(mut function) = compile(
+            self.source.as_str(),
+            &self.args,
+            &mut self.interner,
+            &mut self.globals,
+        ) else {
             return InterpretResult::CompileError;
         };
 
+        if let Err(message) = verify(&function, self.globals.len()) {
+            eprintln!("Invalid bytecode: {message}.");
+            return InterpretResult::CompileError;
+        }
+
+        if self.args.engine == Engine::Register {
+            register::lower(&mut function);
+            if self.args.disassemble {
+                crate::debug::disassemble_registers(&function);
+            }
+        }
+
+        let function = Rc::new(function);
+        let closure = self.heap.alloc(Closure::new(function.clone(), vec![]));
+        let slot_base = self.stack.len();
+        self.push_value(Value::Closure { value: closure });
         self.frames
-            .push(CallFrame::new(Closure::new(function, vec![]), 0, 0));
+            .push(CallFrame::new(closure, function, 0, slot_base));
 
-        self.run()
+        match self.run(0) {
+            InterpretResult::Ok => self.run_event_loop(),
+            result => result,
+        }
     }
 }
 
 impl VM {
     fn populate_native_fn(&mut self) {
-        self.define_native("hello", |_, _| Value::String {
-            value: Rc::new(String::from("Hello, world!")),
+        self.define_native("hello", |vm, _, _| {
+            Ok(Value::String {
+                value: vm.interner.intern("Hello, world!"),
+            })
         });
-    }
+        self.define_native("format", |vm, _, args| {
+            let Some(Value::String { value: template }) = args.first() else {
+                return Err(String::from("format() expects a format string first.").into());
+            };
+
+            // Rendered up front, so instances print through `toString`
+            // just as they do in `print`.
+            let mut rendered = vec![];
+            for arg in &args[1..] {
+                rendered.push(match arg {
+                    Value::Number { value } => format::Arg::Number(*value),
+                    arg => format::Arg::Text(
+                        vm.stringify(arg.clone())
+                            .map_err(|_| NativeError::Reported)?,
+                    ),
+                });
+            }
 
-    fn run(&mut self) -> InterpretResult {
-        trace!("vm::VM::run()");
+            let formatted = format::format(template, &rendered)?;
+            Ok(Value::String {
+                value: vm.interner.intern(&formatted),
+            })
+        });
+        self.define_native("clock", |vm, _, _| {
+            Ok(Value::Number {
+                value: vm.now() / 1000.0,
+            })
+        });
+        self.define_native("sleep", |vm, _, args| {
+            let Some(Value::Number { value: delay }) = args.first() else {
+                return Err(String::from("sleep() expects a delay in milliseconds.").into());
+            };
 
-        loop {
-            #[cfg(feature = "trace_execution")]
-            {
-                // disassemble
-                crate::debug::disassemble_instruction(self.current_chunk(), self.current_cursor());
+            let delay = timer_delay("sleep", *delay)?;
+            let promise = vm.heap.alloc(Promise::new());
+            vm.schedule(delay, TimerAction::Resolve(promise));
+            Ok(Value::Promise { value: promise })
+        });
+        self.define_native("setTimeout", |vm, _, args| {
+            let (Some(callback), Some(Value::Number { value: delay })) =
+                (args.first(), args.get(1))
+            else {
+                return Err(String::from(
+                    "setTimeout() expects a function and a delay in milliseconds.",
+                )
+                .into());
+            };
 
-                // stack trace
-                if self.stack.len() > 0 {
-                    print!("  Stack - ");
-                    for value in self.stack.iter() {
-                        print!("[ {} ]", *value);
-                    }
-                    println!();
+            let arity = match callback {
+                Value::Closure { value } => vm.heap.get(*value).function.arity,
+                Value::BoundMethod { value } => {
+                    let method = vm.heap.get(*value).method;
+                    vm.heap.get(method).function.arity
                 }
+                Value::NativeFn { value: _ } => 0,
+                _ => return Err(String::from("setTimeout() expects a function.").into()),
+            };
+            if arity != 0 {
+                return Err(String::from("setTimeout() callbacks can't take arguments.").into());
             }
 
-            let Some(instruction) = self.next_opcode() else {
-                break;
+            let delay = timer_delay("setTimeout", *delay)?;
+            vm.schedule(delay, TimerAction::Call(callback.clone()));
+            Ok(Value::Nil)
+        });
+    }
+
+    /// Executes frames until the frame stack shrinks back to `depth`, so
+    /// native code can re-enter the loop to run a Lox function to completion.
+    /// Frames left behind by a runtime error are unwound, running their
+    /// deferred closures.
+    fn run(&mut self, depth: usize) -> InterpretResult {
+        trace!("vm::VM::run(depth: {depth})");
+        let result = loop {
+            let result = if self.on_registers() {
+                self.dispatch_registers(depth)
+            } else {
+                self.dispatch(depth)
             };
 
-            match instruction {
-                OpCode::Return => {
-                    let result = self.return_op();
+            match result {
+                // Each engine stops at a frame the other one runs.
+                InterpretResult::Ok if self.frames.len() > depth => {}
+                result => break result,
+            }
+        };
 
-                    match result {
-                        Ok(interpret_finished) => {
-                            if interpret_finished {
-                                return InterpretResult::Ok;
-                            }
-                        }
-                        Err(e) => return e,
+        if let InterpretResult::RuntimeError = result {
+            self.unwind(depth);
+        }
+
+        result
+    }
+
+    /// Runs stack bytecode. The top frame's code, constants and cursor are
+    /// kept in locals: the cursor goes back into the frame only before
+    /// something that can report an error, call into Lox code or switch
+    /// frames, and the locals are reloaded whenever the top frame changes.
+    fn dispatch(&mut self, depth: usize) -> InterpretResult {
+        trace!("vm::VM::dispatch(depth: {depth})");
+
+        'frames: loop {
+            if self.frames.len() == depth || self.on_registers() {
+                return InterpretResult::Ok;
+            }
+
+            let frames = self.frames.len();
+            let frame = self.current_frame();
+            let function = frame.function.clone();
+            let base = frame.slot_base;
+            let mut ip = frame.cursor;
+            let code = function.chunk.instructions.as_slice();
+            let constants = function.chunk.constants.as_slice();
+
+            macro_rules! read_byte {
+                () => {{
+                    // SAFETY: `verify` checked that every instruction has all
+                    // of its operands, that every jump lands on an
+                    // instruction and that the code ends in one that doesn't
+                    // fall through, so `ip` stays inside `code`.
+                    let byte = unsafe { *code.get_unchecked(ip) };
+                    ip += 1;
+                    byte as usize
+                }};
+            }
+
+            macro_rules! read_wide {
+                ($width:expr) => {{
+                    // SAFETY: as for `read_byte!`; `verify` also checked that
+                    // all `$width` bytes of the operand are there.
+                    let bytes = unsafe { code.get_unchecked(ip..ip + $width) };
+                    ip += $width;
+                    decode_operand(bytes)
+                }};
+            }
+
+            macro_rules! read_operand {
+                ($long:expr) => {
+                    if $long { read_wide!(3) } else { read_byte!() }
+                };
+            }
+
+            macro_rules! sync {
+                () => {
+                    self.current_frame_mut().cursor = ip
+                };
+            }
+
+            macro_rules! error {
+                ($message:expr) => {{
+                    sync!();
+                    return self.runtime_error($message);
+                }};
+            }
+
+            // Runs a handler that may report an error or push or pop frames.
+            macro_rules! slow {
+                ($handler:expr) => {{
+                    sync!();
+                    try_or_return!($handler);
+                    if self.frames.len() != frames {
+                        continue 'frames;
                     }
-                }
-                OpCode::Constant => try_or_return!(self.constant()),
-                OpCode::Negate => try_or_return!(self.negate()),
-                OpCode::Not => try_or_return!(self.not()),
-                OpCode::Add => try_or_return!(self.add()),
-                OpCode::Subtract => try_or_return!(self.subtract()),
-                OpCode::Multiply => try_or_return!(self.multiply()),
-                OpCode::Divide => try_or_return!(self.divide()),
-                OpCode::Nil => self.push_value(Value::Nil),
-                OpCode::True => self.push_value(Value::Bool { value: true }),
-                OpCode::False => self.push_value(Value::Bool { value: false }),
-                OpCode::Equal => try_or_return!(self.equal()),
-                OpCode::Greater => try_or_return!(self.greater()),
-                OpCode::Less => try_or_return!(self.less()),
-                OpCode::Print => try_or_return!(self.print()),
-                OpCode::Pop => {
-                    if let None = self.pop_value() {
-                        return self.runtime_error("Invalid access to stack.");
+                }};
+            }
+
+            // Like `slow!`, for a handler that returns a value and leaves
+            // the frames alone.
+            macro_rules! fallible {
+                ($handler:expr) => {{
+                    sync!();
+                    match $handler {
+                        Ok(value) => value,
+                        Err(e) => return e,
                     }
-                }
-                OpCode::DefineGlobal => try_or_return!(self.define_global()),
-                OpCode::GetGlobal => try_or_return!(self.get_global()),
-                OpCode::SetGlobal => try_or_return!(self.set_global()),
-                OpCode::GetLocal => try_or_return!(self.get_local()),
-                OpCode::SetLocal => try_or_return!(self.set_local()),
-                OpCode::JumpIfFalse => try_or_return!(self.jump_if_false()),
-                OpCode::Jump => self.jump(),
-                OpCode::Loop => self.loop_op(),
-                OpCode::Call => try_or_return!(self.call_op()),
-                OpCode::Closure => try_or_return!(self.closure()),
-                OpCode::SetUpvalue => try_or_return!(self.set_upvalue()),
-                OpCode::GetUpvalue => try_or_return!(self.get_upvalue()),
-                OpCode::CloseUpValue => self.close_upvalue(),
-                OpCode::Unknown => return InterpretResult::CompileError,
+                }};
             }
 
-            if self.current_cursor() == self.current_instructions().len() {
-                break;
+            // Two numbers are handled here; anything else goes to `handler`.
+            macro_rules! binary {
+                ($op:expr, $handler:expr) => {
+                    match (self.stack.peek(1), self.stack.peek(0)) {
+                        (Some(Value::Number { value: a }), Some(Value::Number { value: b })) => {
+                            self.stack.truncate(self.stack.len() - 2);
+                            self.push_value($op.numbers(a, b));
+                        }
+                        _ => slow!($handler),
+                    }
+                };
             }
-        }
 
-        InterpretResult::Ok
-    }
+            loop {
+                if self.heap.should_collect(self.args.gc_stress) {
+                    self.collect_garbage();
+                }
 
-    fn next(&mut self) -> Option<usize> {
-        trace!("vm::VM::next()");
-        let frame = self.current_frame_mut();
-        if frame.closure.function.chunk.instructions.len() == 0 {
-            None
-        } else {
-            let instruction = frame.closure.function.chunk.instructions[frame.cursor];
-            frame.cursor += 1;
-            Some(instruction)
-        }
-    }
+                #[cfg(feature = "trace_execution")]
+                {
+                    // disassemble
+                    crate::debug::disassemble_instruction(&function.chunk, ip);
 
-    fn next_opcode(&mut self) -> Option<OpCode> {
-        trace!("VM::next_opcode()");
-        let frame = self.current_frame_mut();
-        if frame.closure.function.chunk.instructions.len() == 0 {
-            None
-        } else {
-            let instruction =
-                OpCode::from_usize(frame.closure.function.chunk.instructions[frame.cursor]);
-            frame.cursor += 1;
-            Some(instruction)
+                    // stack trace
+                    if self.stack.len() > 0 {
+                        print!("  Stack - ");
+                        for value in self.stack.iter() {
+                            print!("[ {} ]", value);
+                        }
+                        println!();
+                    }
+                }
+
+                let opcode = OpCode::from_u8(read_byte!() as u8);
+                match opcode {
+                    OpCode::Return => {
+                        sync!();
+                        try_or_return!(self.return_op());
+                        continue 'frames;
+                    }
+                    OpCode::Constant | OpCode::ConstantLong => {
+                        let index = read_operand!(opcode == OpCode::ConstantLong);
+                        self.push_value(constants[index].clone());
+                    }
+                    OpCode::Negate => match self.stack.peek(0) {
+                        Some(Value::Number { value }) => {
+                            self.pop_value();
+                            self.push_value(Value::Number { value: -value });
+                        }
+                        _ => slow!(self.negate()),
+                    },
+                    OpCode::Not => match self.pop_value() {
+                        Some(value) => self.push_value(Value::Bool {
+                            value: value.is_falsy(),
+                        }),
+                        None => error!("Invalid access to stack"),
+                    },
+                    OpCode::Add => binary!(BinaryOp::Add, self.add()),
+                    OpCode::Subtract => binary!(BinaryOp::Subtract, self.subtract()),
+                    OpCode::Multiply => binary!(BinaryOp::Multiply, self.multiply()),
+                    OpCode::Divide => binary!(BinaryOp::Divide, self.divide()),
+                    OpCode::Nil => self.push_value(Value::Nil),
+                    OpCode::True => self.push_value(Value::Bool { value: true }),
+                    OpCode::False => self.push_value(Value::Bool { value: false }),
+                    OpCode::Equal => binary!(BinaryOp::Equal, self.equal()),
+                    OpCode::Greater => binary!(BinaryOp::Greater, self.greater()),
+                    OpCode::Less => binary!(BinaryOp::Less, self.less()),
+                    OpCode::NotEqual => binary!(BinaryOp::NotEqual, self.negated(Self::equal)),
+                    OpCode::GreaterEqual => {
+                        binary!(BinaryOp::GreaterEqual, self.negated(Self::less))
+                    }
+                    OpCode::LessEqual => {
+                        binary!(BinaryOp::LessEqual, self.negated(Self::greater))
+                    }
+                    OpCode::AddLocalConst => {
+                        let slot = read_byte!();
+                        let constant = constants[read_byte!()].clone();
+                        match (self.stack.get(base + slot), &constant) {
+                            (Some(Value::Number { value: a }), Value::Number { value: b }) => {
+                                self.push_value(Value::Number { value: a + b });
+                            }
+                            _ => slow!(self.add_local_const(slot, constant)),
+                        }
+                    }
+                    OpCode::IncrLocal => {
+                        let slot = read_byte!();
+                        let constant = constants[read_byte!()].clone();
+                        match (self.stack.get(base + slot), &constant) {
+                            (Some(Value::Number { value: a }), Value::Number { value: b }) => {
+                                self.stack.set(base + slot, Value::Number { value: a + b });
+                            }
+                            _ => slow!(self.incr_local(slot, constant)),
+                        }
+                    }
+                    // Two `OP_GET_LOCAL`s in a row. The second local can be
+                    // the value the first one pushes, as when a `case` names
+                    // its scrutinee's slot.
+                    OpCode::GetLocal2 => {
+                        for slot in [read_byte!(), read_byte!()] {
+                            match self.stack.get(base + slot) {
+                                Some(value) => self.push_value(value),
+                                None => error!("Invalid access to stack."),
+                            }
+                        }
+                    }
+                    OpCode::JumpIfLessLocal => {
+                        let slot = read_byte!();
+                        let constant = constants[read_byte!()].clone();
+                        let offset = read_wide!(2);
+                        let jump = match (self.stack.get(base + slot), &constant) {
+                            (Some(Value::Number { value: a }), Value::Number { value: b }) => {
+                                let less = a < *b;
+                                if !less {
+                                    self.push_value(Value::Bool { value: less });
+                                }
+                                !less
+                            }
+                            _ => fallible!(self.jump_if_less_local(slot, constant)),
+                        };
+                        if jump {
+                            ip += offset;
+                        }
+                    }
+                    OpCode::Print => {
+                        let count = read_byte!();
+                        slow!(self.print(count));
+                    }
+                    OpCode::PrintWith => {
+                        let count = read_byte!();
+                        slow!(self.print_with(count));
+                    }
+                    // Suspending the frame ends its run just like returning
+                    // from it.
+                    OpCode::Await => slow!(self.await_op()),
+                    OpCode::Pop => {
+                        if self.pop_value().is_none() {
+                            error!("Invalid access to stack.");
+                        }
+                    }
+                    OpCode::DefineGlobal | OpCode::DefineGlobalLong => {
+                        let slot = read_operand!(opcode == OpCode::DefineGlobalLong);
+                        match self.pop_value() {
+                            Some(value) => self.globals.define(slot, value),
+                            None => error!("Invalid access to stack."),
+                        }
+                    }
+                    OpCode::GetGlobal | OpCode::GetGlobalLong => {
+                        let slot = read_operand!(opcode == OpCode::GetGlobalLong);
+                        match self.globals.get(slot) {
+                            Some(value) => {
+                                let value = value.clone();
+                                self.push_value(value);
+                            }
+                            None => slow!(self.get_global(slot)),
+                        }
+                    }
+                    OpCode::SetGlobal | OpCode::SetGlobalLong => {
+                        let slot = read_operand!(opcode == OpCode::SetGlobalLong);
+                        let Some(value) = self.stack.peek(0) else {
+                            error!("Invalid access to stack.");
+                        };
+                        match self.globals.get_mut(slot) {
+                            Some(global) => *global = value,
+                            None => slow!(self.store_global(slot, value)),
+                        }
+                    }
+                    OpCode::GetLocal | OpCode::GetLocalLong => {
+                        let slot = read_operand!(opcode == OpCode::GetLocalLong);
+                        match self.stack.get(base + slot) {
+                            Some(value) => self.push_value(value),
+                            None => error!("Invalid access to stack."),
+                        }
+                    }
+                    OpCode::SetLocal | OpCode::SetLocalLong => {
+                        let slot = read_operand!(opcode == OpCode::SetLocalLong);
+                        let Some(value) = self.stack.peek(0) else {
+                            error!("Invalid access to stack.");
+                        };
+                        if base + slot >= self.stack.len() {
+                            error!("Invalid access to stack.");
+                        }
+                        self.stack.set(base + slot, value);
+                    }
+                    OpCode::JumpIfFalse => {
+                        let offset = read_wide!(2);
+                        match self.stack.is_falsy_at(0) {
+                            Some(true) => ip += offset,
+                            Some(false) => {}
+                            None => error!("Invalid predicate."),
+                        }
+                    }
+                    OpCode::Jump => {
+                        let offset = read_wide!(2);
+                        ip += offset;
+                    }
+                    OpCode::Loop => {
+                        let offset = read_wide!(2);
+                        ip -= offset;
+                    }
+                    OpCode::Call => {
+                        let arg_count = read_byte!();
+                        slow!(self.call_op(arg_count));
+                    }
+                    OpCode::Closure | OpCode::ClosureLong => {
+                        let constant =
+                            constants[read_operand!(opcode == OpCode::ClosureLong)].clone();
+                        let upvalue_count = match &constant {
+                            Value::Function { value } => value.upvalue_count,
+                            _ => 0,
+                        };
+                        let mut captures = Vec::with_capacity(upvalue_count);
+                        for _ in 0..upvalue_count {
+                            let flags = read_byte!() as u8;
+                            let index = read_operand!(flags & UPVALUE_LONG != 0);
+                            captures.push((flags & UPVALUE_LOCAL != 0, index));
+                        }
+                        let closure = fallible!(self.closure(constant, &captures));
+                        self.push_value(closure);
+                    }
+                    OpCode::SetUpvalue | OpCode::SetUpvalueLong => {
+                        let slot = read_operand!(opcode == OpCode::SetUpvalueLong);
+                        slow!(self.set_upvalue(slot));
+                    }
+                    OpCode::GetUpvalue | OpCode::GetUpvalueLong => {
+                        let slot = read_operand!(opcode == OpCode::GetUpvalueLong);
+                        slow!(self.get_upvalue(slot));
+                    }
+                    OpCode::CloseUpValue => self.close_upvalue(),
+                    OpCode::GetProperty | OpCode::GetPropertyLong => {
+                        let constant =
+                            constants[read_operand!(opcode == OpCode::GetPropertyLong)].clone();
+                        slow!(self.get_property(constant));
+                    }
+                    OpCode::MatchVariant => {
+                        let binding_count = read_byte!();
+                        slow!(self.match_variant(binding_count));
+                    }
+                    OpCode::Destructure => slow!(self.destructure()),
+                    OpCode::Class | OpCode::ClassLong => {
+                        let constant =
+                            constants[read_operand!(opcode == OpCode::ClassLong)].clone();
+                        slow!(self.class(constant));
+                    }
+                    OpCode::Method
+                    | OpCode::MethodLong
+                    | OpCode::Getter
+                    | OpCode::GetterLong
+                    | OpCode::Setter
+                    | OpCode::SetterLong
+                    | OpCode::StaticMethod
+                    | OpCode::StaticMethodLong => {
+                        let (kind, long) = match opcode {
+                            OpCode::Method => (MethodKind::Method, false),
+                            OpCode::MethodLong => (MethodKind::Method, true),
+                            OpCode::Getter => (MethodKind::Getter, false),
+                            OpCode::GetterLong => (MethodKind::Getter, true),
+                            OpCode::Setter => (MethodKind::Setter, false),
+                            OpCode::SetterLong => (MethodKind::Setter, true),
+                            OpCode::StaticMethod => (MethodKind::Static, false),
+                            _ => (MethodKind::Static, true),
+                        };
+                        let constant = constants[read_operand!(long)].clone();
+                        slow!(self.method(kind, constant));
+                    }
+                    OpCode::SetProperty | OpCode::SetPropertyLong => {
+                        let constant =
+                            constants[read_operand!(opcode == OpCode::SetPropertyLong)].clone();
+                        slow!(self.set_property(constant));
+                    }
+                    OpCode::GetIndex => slow!(self.get_index()),
+                    OpCode::SetIndex => slow!(self.set_index()),
+                    OpCode::StaticField | OpCode::StaticFieldLong => {
+                        let constant =
+                            constants[read_operand!(opcode == OpCode::StaticFieldLong)].clone();
+                        slow!(self.static_field(constant));
+                    }
+                    OpCode::Trait | OpCode::TraitLong => {
+                        let constant =
+                            constants[read_operand!(opcode == OpCode::TraitLong)].clone();
+                        slow!(self.trait_op(constant));
+                    }
+                    OpCode::Implement => slow!(self.implement()),
+                    OpCode::Is => slow!(self.is()),
+                    OpCode::JumpIfNil => {
+                        let offset = read_wide!(2);
+                        match self.stack.peek(0) {
+                            Some(Value::Nil) => ip += offset,
+                            Some(_) => {}
+                            None => error!("Invalid access to stack."),
+                        }
+                    }
+                    OpCode::TailCall => {
+                        let arg_count = read_byte!();
+                        sync!();
+                        try_or_return!(self.tail_call_value(arg_count));
+                        continue 'frames;
+                    }
+                    OpCode::Defer => slow!(self.defer()),
+                    OpCode::BuildList => {
+                        let count = read_byte!();
+                        slow!(self.build_list(count));
+                    }
+                    OpCode::ExtendList => {
+                        let count = read_byte!();
+                        slow!(self.extend_list(count));
+                    }
+                    OpCode::Spread => slow!(self.spread()),
+                    OpCode::CallSpread => slow!(self.call_spread()),
+                    OpCode::TailCallSpread => {
+                        sync!();
+                        try_or_return!(self.tail_call_spread());
+                        continue 'frames;
+                    }
+                    OpCode::Assert | OpCode::AssertLong => {
+                        let constant =
+                            constants[read_operand!(opcode == OpCode::AssertLong)].clone();
+                        slow!(self.assert(constant));
+                    }
+                    // `verify` turns unknown opcodes away before anything runs.
+                    OpCode::Unknown => return InterpretResult::CompileError,
+                }
+            }
         }
     }
 
@@ -341,11 +1180,6 @@ impl VM {
         self.frames.last_mut().unwrap()
     }
 
-    #[inline]
-    fn current_cursor(&self) -> usize {
-        self.frames.last().unwrap().cursor
-    }
-
     #[inline]
     fn current_slot_base(&self) -> usize {
         self.frames
@@ -356,53 +1190,7 @@ impl VM {
 
     #[inline]
     fn current_closure(&self) -> &Closure {
-        &self.frames.last().unwrap().closure
-    }
-
-    #[inline]
-    fn current_closure_mut(&mut self) -> &mut Closure {
-        &mut self.frames.last_mut().unwrap().closure
-    }
-
-    #[inline]
-    fn current_function(&self) -> &Function {
-        let frame = self.frames.last().unwrap();
-        &frame.closure.function
-    }
-
-    #[inline]
-    fn current_function_mut(&mut self) -> &mut Function {
-        let frame = self.frames.last_mut().unwrap();
-        &mut frame.closure.function
-    }
-
-    #[inline]
-    fn current_chunk(&self) -> &Chunk {
-        let function = &(self.frames.last().unwrap().closure.function);
-        &function.chunk
-    }
-
-    #[inline]
-    fn current_chunk_mut(&mut self) -> &mut Chunk {
-        let function = &mut (self.frames.last_mut().unwrap().closure.function);
-        &mut function.chunk
-    }
-
-    #[inline]
-    fn current_instructions(&self) -> &Vec<usize> {
-        let chunk = &(self.frames.last().unwrap().closure.function.chunk);
-        &chunk.instructions
-    }
-
-    #[inline]
-    fn current_instructions_mut(&mut self) -> &mut Vec<usize> {
-        let chunk = &mut (self.frames.last_mut().unwrap().closure.function.chunk);
-        &mut chunk.instructions
-    }
-
-    #[inline]
-    fn current_instruction(&self) -> usize {
-        self.current_instructions()[self.current_cursor()]
+        self.heap.get(self.frames.last().unwrap().closure)
     }
 
     fn push_value(&mut self, value: Value) {
@@ -415,45 +1203,16 @@ impl VM {
         self.stack.pop()
     }
 
-    fn top_value(&mut self) -> Option<&Value> {
-        trace!("vm::VM::top_value()");
-        if self.stack.len() == 0 {
-            None
-        } else {
-            Some(&self.stack[self.stack.len() - 1])
-        }
-    }
-
-    fn peek_value_at(&self, at: usize) -> Option<&Value> {
-        if at < self.stack.len() {
-            Some(&self.stack[self.stack.len() - at - 1])
-        } else {
-            None
-        }
-    }
-
-    fn read_constant(&mut self) -> Result<Value, ()> {
-        trace!("vm::VM::read_constant()");
-        let Some(constant_location) = self.next() else {
-            return Err(());
-        };
-
-        let value = self.current_chunk().constants[constant_location].clone();
-
-        Ok(value)
-    }
-
-    fn constant(&mut self) -> Result<(), InterpretResult> {
-        trace!("vm::VM::constant()");
-        let Ok(constant) = self.read_constant() else {
-            return Err(InterpretResult::RuntimeError);
-        };
-        self.push_value(constant);
-        Ok(())
+    fn peek_value_at(&self, at: usize) -> Option<Value> {
+        self.stack.peek(at)
     }
 
     fn negate(&mut self) -> Result<(), InterpretResult> {
         trace!("vm::VM::negate()");
+        if self.invoke_operator("__neg__", 0)? {
+            return Ok(());
+        }
+
         let Some(popped_value) = self.pop_value() else {
             return Err(self.runtime_error("Invalid access to stack"));
         };
@@ -483,6 +1242,10 @@ impl VM {
 
     fn add(&mut self) -> Result<(), InterpretResult> {
         trace!("vm::VM::add()");
+        if self.invoke_binary_operator("__add__", "__radd__")? {
+            return Ok(());
+        }
+
         let (Some(b), Some(a)) = (self.peek_value_at(0), self.peek_value_at(1)) else {
             return Err(self.runtime_error("Invalid access to stack."));
         };
@@ -498,9 +1261,8 @@ impl VM {
                 match (a, b) {
                     (Value::String { value: a_value }, Value::String { value: b_value }) => {
                         let concatenated = format!("{}{}", a_value, b_value);
-                        self.push_value(Value::String {
-                            value: Rc::new(concatenated),
-                        });
+                        let value = self.interner.intern(&concatenated);
+                        self.push_value(Value::String { value });
                     }
                     _ => {}
                 }
@@ -515,208 +1277,351 @@ impl VM {
 
     fn subtract(&mut self) -> Result<(), InterpretResult> {
         trace!("vm::VM::subtract()");
+        if self.invoke_binary_operator("__sub__", "__rsub__")? {
+            return Ok(());
+        }
+
         binary_number_op!(-);
         Ok(())
     }
 
     fn multiply(&mut self) -> Result<(), InterpretResult> {
         trace!("vm::VM::multiply()");
+        if self.invoke_binary_operator("__mul__", "__rmul__")? {
+            return Ok(());
+        }
+
         binary_number_op!(*);
         Ok(())
     }
 
     fn divide(&mut self) -> Result<(), InterpretResult> {
         trace!("vm::VM::divide()");
+        if self.invoke_binary_operator("__div__", "__rdiv__")? {
+            return Ok(());
+        }
+
         binary_number_op!(/);
         Ok(())
     }
 
     fn equal(&mut self) -> Result<(), InterpretResult> {
         trace!("vm::VM::equal()");
-        let (Some(b), Some(a)) = (self.pop_value(), self.pop_value()) else {
+        // Equality is symmetric, so a right-hand instance reflects to itself.
+        if self.invoke_binary_operator("__eq__", "__eq__")? {
+            return Ok(());
+        }
+
+        let Some(equal) = self.stack.pop_equal(&self.heap) else {
             return Err(self.runtime_error("Invalid access to stack"));
         };
 
-        self.push_value(Value::Bool { value: a.eq(b) });
+        self.push_value(Value::Bool { value: equal });
 
         Ok(())
     }
 
     fn greater(&mut self) -> Result<(), InterpretResult> {
         trace!("vm::VM::greater()");
-        binary_bool_op!(>);
+        if self.invoke_binary_operator("__gt__", "__lt__")? {
+            return Ok(());
+        }
+
+        binary_bool_op!(>);
         Ok(())
     }
 
     fn less(&mut self) -> Result<(), InterpretResult> {
         trace!("vm::VM::less()");
+        if self.invoke_binary_operator("__lt__", "__gt__")? {
+            return Ok(());
+        }
+
         binary_bool_op!(<);
         Ok(())
     }
 
-    fn print(&mut self) -> Result<(), InterpretResult> {
-        trace!("vm::VM::print()");
-        let Some(popped_value) = self.pop_value() else {
-            return Err(self.runtime_error("Invalid access to stack."));
-        };
+    /// Runs `compare` and negates its result, for the opcodes that fuse a
+    /// comparison with `OP_NOT`. An operator method the comparison calls
+    /// is run to completion first, so there is a result to negate.
+    fn negated(
+        &mut self,
+        compare: fn(&mut Self) -> Result<(), InterpretResult>,
+    ) -> Result<(), InterpretResult> {
+        trace!("vm::VM::negated()");
+        let depth = self.frames.len();
+        compare(self)?;
+        self.finish_operator(depth)?;
+        self.not()
+    }
+
+    /// Runs the operator method an arithmetic or comparison op called, if
+    /// it called one, until it returns to `depth` frames.
+    fn finish_operator(&mut self, depth: usize) -> Result<(), InterpretResult> {
+        trace!("vm::VM::finish_operator(depth: {depth})");
+        if self.frames.len() == depth {
+            return Ok(());
+        }
 
-        println!("{popped_value}");
-        Ok(())
+        match self.run(depth) {
+            InterpretResult::Ok => Ok(()),
+            e => Err(e),
+        }
     }
 
-    fn define_global(&mut self) -> Result<(), InterpretResult> {
-        trace!("vm::VM::define_global()");
-        let Ok(name) = self.read_constant() else {
-            return Err(self.runtime_error("Identifier name not found."));
-        };
+    /// `OP_GET_LOCAL`, `OP_CONSTANT`, `OP_ADD`.
+    fn add_local_const(&mut self, slot: usize, constant: Value) -> Result<(), InterpretResult> {
+        trace!("vm::VM::add_local_const()");
+        let local = self.local(slot)?;
 
-        match name {
-            Value::String { value } => {
-                let Some(peeked) = self.peek_value_at(0) else {
-                    return Err(self.runtime_error("Invalid access to stack."));
-                };
+        if let (Value::Number { value: a }, Value::Number { value: b }) = (&local, &constant) {
+            self.push_value(Value::Number { value: a + b });
+            return Ok(());
+        }
+
+        // An `__add__` method can be left to return its result itself.
+        self.push_value(local);
+        self.push_value(constant);
+        self.add()
+    }
 
-                self.globals.insert(value.clone(), peeked.clone());
-                _ = self.pop_value().unwrap();
+    /// `OP_ADD_LOCAL_CONST`, then `OP_SET_LOCAL` to the same slot and
+    /// `OP_POP`: `i = i + 1;` without touching the top of the stack.
+    fn incr_local(&mut self, slot: usize, constant: Value) -> Result<(), InterpretResult> {
+        trace!("vm::VM::incr_local()");
+        let local = self.local(slot)?;
+
+        let sum = match (&local, &constant) {
+            (Value::Number { value: a }, Value::Number { value: b }) => {
+                Value::Number { value: a + b }
             }
-            _ => return Err(self.runtime_error("Invalid name for identifier.")),
-        }
+            // The sum is stored once an `__add__` method has returned it.
+            _ => {
+                let depth = self.frames.len();
+                self.push_value(local);
+                self.push_value(constant);
+                self.add()?;
+                self.finish_operator(depth)?;
+                self.pop_value()
+                    .ok_or_else(|| self.runtime_error("Invalid access to stack."))?
+            }
+        };
+        let base = self.current_slot_base();
+        self.stack.set(base + slot, sum);
 
         Ok(())
     }
 
-    fn get_global(&mut self) -> Result<(), InterpretResult> {
-        trace!("vm::VM::get_global()");
-        let Ok(name_value) = self.read_constant() else {
-            return Err(self.runtime_error("Identifier name not found."));
+    /// `OP_GET_LOCAL`, `OP_CONSTANT`, `OP_LESS`, `OP_JUMP_IF_FALSE` and the
+    /// `OP_POP` after it. Returns false when the local is less than the
+    /// constant, and otherwise true with the comparison's result on the
+    /// stack, for the `OP_POP` at the jump target.
+    fn jump_if_less_local(
+        &mut self,
+        slot: usize,
+        constant: Value,
+    ) -> Result<bool, InterpretResult> {
+        trace!("vm::VM::jump_if_less_local()");
+        let local = self.local(slot)?;
+
+        let less = match (&local, &constant) {
+            (Value::Number { value: a }, Value::Number { value: b }) => {
+                Value::Bool { value: a < b }
+            }
+            _ => {
+                let depth = self.frames.len();
+                self.push_value(local);
+                self.push_value(constant);
+                self.less()?;
+                self.finish_operator(depth)?;
+                self.pop_value()
+                    .ok_or_else(|| self.runtime_error("Invalid access to stack."))?
+            }
         };
 
-        match name_value {
-            Value::String { value: name } => {
-                let Some(value) = self.globals.get(&name) else {
-                    return Err(
-                        self.runtime_error(format!("Global Variable {name} not found").as_str())
-                    );
-                };
-                self.push_value(value.clone());
-            }
-            _ => return Err(self.runtime_error("Invalid name for identifier.")),
+        if less.is_falsy() {
+            self.push_value(less);
+            return Ok(true);
         }
 
-        Ok(())
+        Ok(false)
     }
 
-    fn set_global(&mut self) -> Result<(), InterpretResult> {
-        trace!("vm::VM::set_global()");
-        let Ok(name_value) = self.read_constant() else {
-            return Err(self.runtime_error("Identifier name not found."));
-        };
+    fn print(&mut self, count: usize) -> Result<(), InterpretResult> {
+        trace!("vm::VM::print()");
+        self.print_values(count, String::from(" "), String::from("\n"))
+    }
 
-        let Value::String { value: name } = name_value else {
-            return Err(self.runtime_error("Invalid name for identifier."));
+    fn print_with(&mut self, count: usize) -> Result<(), InterpretResult> {
+        trace!("vm::VM::print_with()");
+        // The options stay on the stack while `toString` runs, so the
+        // collector can still see them.
+        let (Some(end), Some(separator)) = (self.peek_value_at(0), self.peek_value_at(1)) else {
+            return Err(self.runtime_error("Invalid access to stack."));
         };
 
-        let Some(value) = self.peek_value_at(0) else {
-            return Err(self.runtime_error("Invalid access to stack."));
+        let separator = match separator {
+            Value::Nil => String::from(" "),
+            separator => self.stringify(separator)?,
+        };
+        let end = match end {
+            Value::Nil => String::from("\n"),
+            end => self.stringify(end)?,
         };
 
-        if !self.globals.contains_key(&name) {
-            return Err(self.runtime_error(&format!("Undefined variable {}.", name)));
+        self.pop_value();
+        self.pop_value();
+        self.print_values(count, separator, end)
+    }
+
+    /// Prints the top `count` values and pops them.
+    fn print_values(
+        &mut self,
+        count: usize,
+        separator: String,
+        end: String,
+    ) -> Result<(), InterpretResult> {
+        if self.stack.len() < count {
+            return Err(self.runtime_error("Invalid access to stack."));
         }
 
-        *self.globals.get_mut(&name).unwrap() = value.clone();
+        let start = self.stack.len() - count;
+        let mut output = String::new();
+        for i in 0..count {
+            if i > 0 {
+                output.push_str(&separator);
+            }
+            let value = self.stack.get(start + i).unwrap();
+            output.push_str(&self.stringify(value)?);
+        }
+        output.push_str(&end);
+        self.stack.truncate(start);
 
+        print!("{output}");
+        io::stdout().flush().unwrap();
         Ok(())
     }
 
-    fn get_local(&mut self) -> Result<(), InterpretResult> {
-        trace!("vm::VM::get_local()");
-        let Some(slot) = self.next() else {
-            return Err(self.runtime_error("Cannot get slot for local variable."));
-        };
+    /// Text shown by `print`, going through `toString` for instances that
+    /// define it. The value must be reachable from the stack, as `toString`
+    /// may trigger a collection.
+    fn stringify(&mut self, value: Value) -> Result<String, InterpretResult> {
+        if let Value::Instance { value: instance } = &value {
+            let to_string = self.class_of(*instance).find_method("toString");
+            if let Some(method) = to_string {
+                let bound = self.heap.alloc(BoundMethod::new(value.clone(), method));
+                let result = self.call_function(Value::BoundMethod { value: bound }, vec![])?;
+                return Ok(result.display(&self.heap).to_string());
+            }
+        }
 
-        let base = self.current_slot_base();
-        let index = base + slot;
+        Ok(value.display(&self.heap).to_string())
+    }
 
-        let Some(value) = self.stack.get(index) else {
-            return Err(self.runtime_error("Invalid access to stack."));
-        };
+    fn class_of(&self, instance: Handle<Instance>) -> &Class {
+        self.heap.get(self.heap.get(instance).class)
+    }
 
-        self.push_value(value.clone());
+    fn get_global(&mut self, slot: usize) -> Result<(), InterpretResult> {
+        trace!("vm::VM::get_global()");
+        let value = self.global(slot)?;
+        self.push_value(value);
 
         Ok(())
     }
 
-    fn set_local(&mut self) -> Result<(), InterpretResult> {
-        trace!("vm::VM::set_local()");
-        let Some(slot) = self.next() else {
-            return Err(self.runtime_error("Cannot get slot for local variable."));
+    fn global(&self, slot: usize) -> Result<Value, InterpretResult> {
+        let Some(value) = self.globals.get(slot) else {
+            let name = self.globals.name(slot);
+            return Err(self.runtime_error(format!("Global Variable {name} not found").as_str()));
         };
 
-        let value = self
-            .peek_value_at(0)
-            .ok_or_else(|| self.runtime_error("Invalid access to stack."))?
-            .clone();
-
-        let base = self.current_slot_base();
-        let index = base + slot;
+        Ok(value.clone())
+    }
 
-        let Some(slot_ref) = self.stack.get_mut(index) else {
-            return Err(self.runtime_error("Invalid access to stack."));
+    fn store_global(&mut self, slot: usize, value: Value) -> Result<(), InterpretResult> {
+        let Some(global) = self.globals.get_mut(slot) else {
+            let name = self.globals.name(slot);
+            return Err(self.runtime_error(&format!("Undefined variable {}.", name)));
         };
-
-        *slot_ref = value;
+        *global = value;
 
         Ok(())
     }
 
-    fn jump_if_false(&mut self) -> Result<(), InterpretResult> {
-        trace!("vm::VM::jump_if_false()");
+    /// The value in local `slot` of the current frame.
+    fn local(&self, slot: usize) -> Result<Value, InterpretResult> {
+        let index = self.current_slot_base() + slot;
+        self.stack
+            .get(index)
+            .ok_or_else(|| self.runtime_error("Invalid access to stack."))
+    }
 
-        let offset = self.current_instruction();
-        {
-            self.current_frame_mut().cursor += 1;
-        }
+    fn call_op(&mut self, arg_count: usize) -> Result<(), InterpretResult> {
+        trace!("vm::VM::call()");
 
-        let Some(predicate) = self.peek_value_at(0) else {
-            return Err(self.runtime_error("Invalid predicate."));
-        };
+        let value = self
+            .peek_value_at(arg_count)
+            .expect("Invalid access to stack.");
 
-        if predicate.is_falsy() {
-            self.current_frame_mut().cursor += offset;
+        if !self.call_value(value, arg_count) {
+            return Err(InterpretResult::RuntimeError);
         }
 
         Ok(())
     }
 
-    fn jump(&mut self) {
-        trace!("vm::VM::jump()");
-
-        let offset = self.current_instruction();
-        self.current_frame_mut().cursor += offset + 1;
-    }
-
-    fn loop_op(&mut self) {
-        trace!("vm::VM::loop_op()");
+    /// Calls the value below the top `arg_count` values in place of the
+    /// current frame, when nothing is left for the frame to do.
+    fn tail_call_value(&mut self, arg_count: usize) -> Result<(), InterpretResult> {
+        let value = self
+            .peek_value_at(arg_count)
+            .ok_or_else(|| self.runtime_error("Invalid access to stack."))?;
+        let callee_slot = self.stack.len() - arg_count - 1;
 
-        let offset = self.current_instruction();
-        self.current_frame_mut().cursor -= offset;
-    }
+        let keep_frame =
+            !self.current_frame().deferred.is_empty() || self.current_frame().promise.is_some();
 
-    fn call_op(&mut self) -> Result<(), InterpretResult> {
-        trace!("vm::VM::call()");
+        let closure = match value {
+            Value::Closure { value } if !keep_frame && !self.heap.get(value).function.is_async => {
+                value
+            }
+            Value::BoundMethod { value }
+                if !keep_frame && !self.heap.get(self.heap.get(value).method).function.is_async =>
+            {
+                let bound = self.heap.get(value);
+                self.stack.set(callee_slot, bound.receiver.clone());
+                bound.method
+            }
+            // Natives and constructors don't need a frame of their own, and a
+            // frame with pending deferred closures or a promise to resolve
+            // must outlive the callee, as must the frame of an async callee.
+            // The `OP_RETURN` that follows returns the result.
+            value => {
+                if !self.call_value(value, arg_count) {
+                    return Err(InterpretResult::RuntimeError);
+                }
+                return Ok(());
+            }
+        };
 
-        let arg_count = self.current_instruction();
-        self.current_frame_mut().cursor += 1;
+        let arity = self.heap.get(closure).function.arity;
+        if arity != arg_count {
+            let message = format!("Expected {} arguments but got {}.", arity, arg_count);
+            return Err(self.runtime_error(message.as_str()));
+        }
 
-        let value = self
-            .peek_value_at(arg_count)
-            .expect("Invalid access to stack.")
-            .clone();
+        // Slide the callee and its arguments down over the current frame's
+        // window and restart the frame with the new closure.
+        let slot_base = self.current_slot_base();
+        self.close_upvalues_from(slot_base);
+        self.stack.remove(slot_base..callee_slot);
 
-        if !self.call_value(value, arg_count) {
-            return Err(InterpretResult::RuntimeError);
-        }
+        let function = self.heap.get(closure).function.clone();
+        let frame = self.current_frame_mut();
+        frame.closure = closure;
+        frame.function = function;
+        frame.cursor = 0;
 
         Ok(())
     }
@@ -732,8 +1637,15 @@ impl VM {
                 }
 
                 let args_start = self.stack.len() - arg_count;
-                let args = self.stack[args_start..].to_vec();
-                let result = (value.function)(arg_count, args);
+                let args = self.stack.values_from(args_start);
+                let result = match (value.function)(self, arg_count, args) {
+                    Ok(result) => result,
+                    Err(NativeError::Message(message)) => {
+                        _ = self.runtime_error(message.as_str());
+                        return false;
+                    }
+                    Err(NativeError::Reported) => return false,
+                };
 
                 for _ in 0..=arg_count {
                     self.stack.pop();
@@ -743,6 +1655,55 @@ impl VM {
 
                 true
             }
+            Value::BoundMethod { value } => {
+                let callee_slot = self.stack.len() - arg_count - 1;
+                let bound = self.heap.get(value);
+                self.stack.set(callee_slot, bound.receiver.clone());
+                self.call(bound.method, arg_count)
+            }
+            Value::Class { value: class } => {
+                let callee_slot = self.stack.len() - arg_count - 1;
+                let initializer = self.heap.get(class).find_method("init");
+                let instance = self.heap.alloc(Instance::new(class));
+                self.stack
+                    .set(callee_slot, Value::Instance { value: instance });
+
+                match initializer {
+                    Some(initializer) => self.call(initializer, arg_count),
+                    None if arg_count != 0 => {
+                        let message = format!("Expected 0 arguments but got {}.", arg_count);
+                        _ = self.runtime_error(message.as_str());
+                        false
+                    }
+                    None => true,
+                }
+            }
+            Value::VariantConstructor { value } => {
+                if value.arity() != arg_count {
+                    let message = format!(
+                        "Expected {} arguments but got {}.",
+                        value.arity(),
+                        arg_count
+                    );
+                    _ = self.runtime_error(message.as_str());
+                    return false;
+                }
+
+                if self.stack.len() < arg_count + 1 {
+                    _ = self.runtime_error("Invalid access to stack.");
+                    return false;
+                }
+
+                let args_start = self.stack.len() - arg_count;
+                let payload = self.stack.split_off(args_start);
+                self.stack.pop();
+
+                self.push_value(Value::Variant {
+                    value: Rc::new(Variant::new(value.enum_def, value.variant, payload)),
+                });
+
+                true
+            }
             _ => {
                 _ = self.runtime_error("Can only call functions and classes.");
                 false
@@ -750,12 +1711,13 @@ impl VM {
         }
     }
 
-    fn call(&mut self, closure: Closure, arg_count: usize) -> bool {
+    fn call(&mut self, closure: Handle<Closure>, arg_count: usize) -> bool {
         trace!("vm::VM::call(function, arg_count: {arg_count})");
-        if closure.function.arity != arg_count {
+        let function = self.heap.get(closure).function.clone();
+        if function.arity != arg_count {
             let message = format!(
                 "Expected {} arguments but got {}.",
-                closure.function.arity, arg_count
+                function.arity, arg_count
             );
             _ = self.runtime_error(message.as_str());
             return false;
@@ -772,7 +1734,11 @@ impl VM {
         }
 
         let slot_base = self.stack.len() - arg_count - 1;
-        let frame = CallFrame::new(closure, 0, slot_base);
+        let is_async = function.is_async;
+        let mut frame = CallFrame::new(closure, function, 0, slot_base);
+        if is_async {
+            frame.promise = Some(self.heap.alloc(Promise::new()));
+        }
         self.frames.push(frame);
 
         true
@@ -780,6 +1746,13 @@ impl VM {
 
     fn return_op(&mut self) -> Result<bool, InterpretResult> {
         trace!("vm::VM::return_op()");
+        // Deferred closures run last-in first-out. Popping them one at a
+        // time leaves the rest for `unwind` if one of them fails. The result
+        // stays on the stack meanwhile so a collection can't free it.
+        while let Some(closure) = self.current_frame_mut().deferred.pop() {
+            self.call_function(Value::Closure { value: closure }, vec![])?;
+        }
+
         let Some(result) = self.pop_value() else {
             return Err(self.runtime_error("Invalid access to stack."));
         };
@@ -791,6 +1764,16 @@ impl VM {
 
         self.close_upvalues_from(frame.slot_base);
 
+        // Async calls hand their caller the promise, whether or not the body
+        // suspended on the way.
+        let result = match frame.promise {
+            Some(promise) => {
+                self.resolve_promise(promise, result);
+                Value::Promise { value: promise }
+            }
+            None => result,
+        };
+
         if self.frames.len() == 0 {
             self.stack.clear();
             self.push_value(result);
@@ -803,186 +1786,1278 @@ impl VM {
         Ok(false)
     }
 
-    fn define_native(&mut self, name: &str, function: fn(usize, Vec<Value>) -> Value) {
-        trace!("vm::VM::define_native(name: {name}, function)");
-        let function = NativeFn::new(name, function);
+    /// Pushes the value of a resolved promise. A pending one suspends the
+    /// current async frame into a `Task` queued on the promise, and the
+    /// frame's own promise becomes the result of the call that started it.
+    fn await_op(&mut self) -> Result<(), InterpretResult> {
+        trace!("vm::VM::await_op()");
+        let Some(value) = self.pop_value() else {
+            return Err(self.runtime_error("Invalid access to stack."));
+        };
 
-        self.push_value(Value::String {
-            value: Rc::new(String::from(name)),
-        });
-        self.push_value(Value::NativeFn { value: function });
-        let function = self.peek_value_at(0).unwrap();
-        let name = self.peek_value_at(1).unwrap();
+        // Awaiting anything but a promise just yields the value itself.
+        let Value::Promise { value: promise } = value else {
+            self.push_value(value);
+            return Ok(());
+        };
 
-        match name {
-            Value::String { value: name } => {
-                self.globals.insert(name.clone(), function.clone());
+        let resolved = self.heap.get(promise).value().cloned();
+        if let Some(resolved) = resolved {
+            self.push_value(resolved);
+            return Ok(());
+        }
+
+        let Some(own_promise) = self.current_frame().promise else {
+            return Err(self.runtime_error("Can only await inside an async function."));
+        };
+
+        let frame = self
+            .frames
+            .pop()
+            .expect("await_op cannot run without an active call frame");
+        let base = frame.slot_base;
+
+        // Upvalues into the frame are closed while it is suspended so other
+        // closures can still use them, and reopened on resume.
+        let start = self.open_upvalues.partition_point(|(i, _)| *i < base);
+        let mut upvalues = vec![];
+        for (index, upvalue) in self.open_upvalues.drain(start..) {
+            self.heap
+                .get_mut(upvalue)
+                .close(self.stack.get(index).unwrap());
+            upvalues.push((index - base, upvalue));
+        }
+
+        let stack = self.stack.split_off(base);
+        if let PromiseState::Pending { waiters } = &mut self.heap.get_mut(promise).state {
+            waiters.push(Task::new(frame, stack, upvalues));
+        }
+
+        self.push_value(Value::Promise { value: own_promise });
+
+        Ok(())
+    }
+
+    fn defer(&mut self) -> Result<(), InterpretResult> {
+        trace!("vm::VM::defer()");
+        let Some(Value::Closure { value: closure }) = self.pop_value() else {
+            return Err(self.runtime_error("Only closures can be deferred."));
+        };
+
+        self.current_frame_mut().deferred.push(closure);
+
+        Ok(())
+    }
+
+    fn unwind(&mut self, depth: usize) {
+        trace!("vm::VM::unwind(depth: {depth})");
+
+        while self.frames.len() > depth {
+            while let Some(closure) = self.current_frame_mut().deferred.pop() {
+                // A failing deferred closure reports its own error; the
+                // remaining ones still run.
+                _ = self.call_function(Value::Closure { value: closure }, vec![]);
             }
-            _ => {}
+
+            let frame = self
+                .frames
+                .pop()
+                .expect("unwind cannot run without an active call frame");
+            self.close_upvalues_from(frame.slot_base);
+            self.stack.truncate(frame.slot_base);
         }
+    }
 
-        self.pop_value();
-        self.pop_value();
+    fn define_native(
+        &mut self,
+        name: &str,
+        function: fn(&mut VM, usize, Vec<Value>) -> NativeResult,
+    ) {
+        trace!("vm::VM::define_native(name: {name}, function)");
+        let function = NativeFn::new(name, function);
+
+        let name = self.interner.intern(name);
+        let slot = self.globals.slot(name);
+        self.globals
+            .define(slot, Value::NativeFn { value: function });
     }
 
-    fn closure(&mut self) -> Result<(), InterpretResult> {
-        trace!("vm::VM::closure()");
-        let Ok(constant) = self.read_constant() else {
-            return Err(InterpretResult::RuntimeError);
+    fn get_property(&mut self, constant: Value) -> Result<(), InterpretResult> {
+        trace!("vm::VM::get_property()");
+        let Value::String { value: name } = constant else {
+            return Err(self.runtime_error("Invalid name for property."));
         };
+        self.get_property_of(name)
+    }
 
-        match constant {
-            Value::Function { value: function } => {
-                let mut closure = Closure::new(function, vec![]);
+    /// Replaces the receiver on top of the stack with its property `name`.
+    /// A getter is left to push the value itself when it returns.
+    fn get_property_of(&mut self, name: InternedString) -> Result<(), InterpretResult> {
+        let Some(receiver) = self.pop_value() else {
+            return Err(self.runtime_error("Invalid access to stack."));
+        };
 
-                for _ in 0..closure.function.upvalue_count {
-                    let Some(is_local) = self.next_opcode() else {
-                        return Err(self.runtime_error(""));
-                    };
-                    let Some(index) = self.next() else {
-                        return Err(self.runtime_error(""));
-                    };
+        match receiver {
+            Value::Class { value: class } => {
+                let field = self
+                    .heap
+                    .get(class)
+                    .static_fields
+                    .get(name.as_str())
+                    .cloned();
+                if let Some(field) = field {
+                    self.push_value(field);
+                    return Ok(());
+                }
 
-                    match is_local {
-                        OpCode::True => {
-                            let id = self.capture_upvalue(index)?;
+                let method = self
+                    .heap
+                    .get(class)
+                    .static_methods
+                    .get(name.as_str())
+                    .copied();
+                let Some(method) = method else {
+                    return Err(
+                        self.runtime_error(format!("Undefined property '{name}'.").as_str())
+                    );
+                };
 
-                            closure.upvalues.push(id);
-                        }
-                        OpCode::False => {
-                            let id =
-                                *self.current_closure().upvalues.get(index).ok_or_else(|| {
-                                    self.runtime_error("Bad outer upvalue index.")
-                                })?;
+                let bound = self
+                    .heap
+                    .alloc(BoundMethod::new(Value::Class { value: class }, method));
+                self.push_value(Value::BoundMethod { value: bound });
+            }
+            Value::Enum { value: enum_def } => {
+                let Some(variant) = enum_def.find_variant(&name) else {
+                    return Err(self.runtime_error(
+                        format!("Undefined variant {name} on enum {}.", enum_def.name).as_str(),
+                    ));
+                };
 
-                            closure.upvalues.push(id);
-                        }
-                        _ => return Err(self.runtime_error("")),
+                if enum_def.variants[variant].arity == 0 {
+                    self.push_value(Value::Variant {
+                        value: Rc::new(Variant::new(enum_def, variant, vec![])),
+                    });
+                } else {
+                    self.push_value(Value::VariantConstructor {
+                        value: VariantConstructor::new(enum_def, variant),
+                    });
+                }
+            }
+            Value::Instance { value: instance } => {
+                // Accessors shadow raw fields so a class can replace a plain
+                // field with a getter without breaking callers.
+                if let Some(getter) = self.class_of(instance).find_getter(name.as_str()) {
+                    self.push_value(Value::Instance { value: instance });
+                    if !self.call(getter, 0) {
+                        return Err(InterpretResult::RuntimeError);
                     }
+                    return Ok(());
                 }
 
-                let value = Value::Closure { value: closure };
+                let field = self.heap.get(instance).fields.get(name.as_str()).cloned();
+                if let Some(field) = field {
+                    self.push_value(field);
+                    return Ok(());
+                }
+
+                let Some(method) = self.class_of(instance).find_method(name.as_str()) else {
+                    return Err(
+                        self.runtime_error(format!("Undefined property '{name}'.").as_str())
+                    );
+                };
 
-                self.push_value(value);
+                let bound = self.heap.alloc(BoundMethod::new(
+                    Value::Instance { value: instance },
+                    method,
+                ));
+                self.push_value(Value::BoundMethod { value: bound });
             }
-            _ => return Err(self.runtime_error("Invalid function object.")),
+            _ => return Err(self.runtime_error("Only instances and enums have properties.")),
         }
 
         Ok(())
     }
 
-    fn capture_upvalue(&mut self, index: usize) -> Result<usize, InterpretResult> {
-        trace!("vm::VM::capture_upvalue(index: {index})");
-        let index = self.current_slot_base() + index;
-
-        match self.open_upvalues.binary_search_by_key(&index, |(i, _)| *i) {
-            Ok(pos) => Ok(self.open_upvalues[pos].1),
-            Err(pos) => {
-                let id = self.upvalues.len();
-                self.upvalues.push(Upvalue::new_open(index));
-                self.open_upvalues.insert(pos, (index, id));
-                Ok(id)
-            }
-        }
+    fn set_property(&mut self, constant: Value) -> Result<(), InterpretResult> {
+        trace!("vm::VM::set_property()");
+        let Value::String { value: name } = constant else {
+            return Err(self.runtime_error("Invalid name for property."));
+        };
+        self.set_property_of(name)
     }
 
-    fn set_upvalue(&mut self) -> Result<(), InterpretResult> {
-        trace!("vm::VM::set_upvalue()");
-
-        let Some(slot) = self.next() else {
-            return Err(InterpretResult::RuntimeError);
+    /// Assigns the value on top of the stack to property `name` of the
+    /// receiver below it, and leaves the value in their place. A setter is
+    /// left to push its result itself when it returns.
+    fn set_property_of(&mut self, name: InternedString) -> Result<(), InterpretResult> {
+        let (Some(value), Some(receiver)) = (self.pop_value(), self.pop_value()) else {
+            return Err(self.runtime_error("Invalid access to stack."));
         };
 
-        let value = self
-            .peek_value_at(0)
-            .ok_or_else(|| self.runtime_error("Stack underflow."))?
-            .clone();
-
-        let up_id = *self
-            .current_closure()
-            .upvalues
-            .get(slot)
-            .ok_or_else(|| self.runtime_error("Bad upvalue slot."))?;
+        match receiver {
+            Value::Instance { value: instance } => {
+                let setter = self.class_of(instance).find_setter(name.as_str());
+                if let Some(setter) = setter {
+                    self.push_value(Value::Instance { value: instance });
+                    self.push_value(value);
+                    if !self.call(setter, 1) {
+                        return Err(InterpretResult::RuntimeError);
+                    }
+                    return Ok(());
+                }
 
-        match self.upvalues[up_id].loc {
-            UpvalueLoc::Open(i) => {
-                let s = self.stack.get_mut(i).unwrap();
-                *s = value;
+                self.heap
+                    .get_mut(instance)
+                    .fields
+                    .insert(name.to_string(), value.clone());
             }
-            UpvalueLoc::Closed => {
-                self.upvalues[up_id].value = Rc::new(value);
+            Value::Class { value: class } => {
+                self.heap
+                    .get_mut(class)
+                    .static_fields
+                    .insert(name.to_string(), value.clone());
             }
+            _ => return Err(self.runtime_error("Only instances and classes have fields.")),
         }
 
+        self.push_value(value);
+
         Ok(())
     }
 
-    fn get_upvalue(&mut self) -> Result<(), InterpretResult> {
-        trace!("vm::VM::get_upvalue()");
+    fn get_index(&mut self) -> Result<(), InterpretResult> {
+        trace!("vm::VM::get_index()");
+        if self.invoke_operator("__get__", 1)? {
+            return Ok(());
+        }
 
-        let Some(slot) = self.next() else {
-            return Err(self.runtime_error("Bad upvalue."));
+        let (Some(index), Some(Value::List { value: list })) = (self.pop_value(), self.pop_value())
+        else {
+            return Err(self.runtime_error("Only lists and instances with __get__ can be indexed."));
         };
 
-        let up_id = *self
-            .current_closure()
-            .upvalues
-            .get(slot)
-            .ok_or_else(|| self.runtime_error("Bad upvalue slot."))?;
+        let index = self.list_index(&index, self.heap.get(list).len())?;
+        let item = self.heap.get(list)[index].clone();
+        self.push_value(item);
 
-        let value = match self.upvalues[up_id].loc {
-            UpvalueLoc::Open(i) => self
-                .stack
-                .get(i)
-                .ok_or_else(|| self.runtime_error("Stack out of boundary."))?
-                .clone(),
-            UpvalueLoc::Closed => self.upvalues[up_id].value.deref().clone(),
+        Ok(())
+    }
+
+    fn set_index(&mut self) -> Result<(), InterpretResult> {
+        trace!("vm::VM::set_index()");
+        if self.invoke_operator("__set__", 2)? {
+            return Ok(());
+        }
+
+        let (Some(value), Some(index), Some(Value::List { value: list })) =
+            (self.pop_value(), self.pop_value(), self.pop_value())
+        else {
+            return Err(self
+                .runtime_error("Only lists and instances with __set__ support index assignment."));
         };
 
+        let index = self.list_index(&index, self.heap.get(list).len())?;
+        self.heap.get_mut(list)[index] = value.clone();
         self.push_value(value);
 
         Ok(())
     }
 
-    fn close_upvalue(&mut self) {
-        if let Some(top) = self.stack.len().checked_sub(1) {
-            let start = self.open_upvalues.partition_point(|(i, _)| *i < top);
-            let end = self.open_upvalues.partition_point(|(i, _)| *i <= top);
-
-            for (_, id) in self.open_upvalues.drain(start..end) {
-                if let UpvalueLoc::Open(idx) = self.upvalues[id].loc {
-                    let value = self.stack[idx].clone();
-                    self.upvalues[id].value = Rc::new(value);
-                    self.upvalues[id].loc = UpvalueLoc::Closed;
-                }
-            }
+    fn list_index(&self, index: &Value, len: usize) -> Result<usize, InterpretResult> {
+        let Value::Number { value: index } = *index else {
+            return Err(self.runtime_error("List index must be a number."));
+        };
+
+        if index < 0.0 || index.fract() != 0.0 {
+            return Err(self.runtime_error("List index must be a non-negative integer."));
         }
-    }
 
-    fn close_upvalues_from(&mut self, from: usize) {
-        trace!("vm::VM::close_upvalues(from: {from})");
+        if index as usize >= len {
+            return Err(self.runtime_error(
+                format!("List index {index} out of range for length {len}.").as_str(),
+            ));
+        }
 
-        let start = self.open_upvalues.partition_point(|(i, _)| *i < from);
+        Ok(index as usize)
+    }
 
-        for (_, id) in self.open_upvalues.drain(start..) {
-            if let UpvalueLoc::Open(i) = self.upvalues[id].loc {
-                let value = self.stack[i].clone();
-                self.upvalues[id].value = Rc::new(value);
-                self.upvalues[id].loc = UpvalueLoc::Closed;
-            }
+    fn build_list(&mut self, count: usize) -> Result<(), InterpretResult> {
+        trace!("vm::VM::build_list()");
+
+        if self.stack.len() < count {
+            return Err(self.runtime_error("Invalid access to stack."));
         }
+
+        let items = self.stack.split_off(self.stack.len() - count);
+        let list = self.heap.alloc(items);
+        self.push_value(Value::List { value: list });
+
+        Ok(())
     }
-}
 
-impl VM {
-    fn runtime_error(&self, message: &str) -> InterpretResult {
-        let loc = self.current_chunk().loc[self.current_cursor()].clone();
+    fn extend_list(&mut self, count: usize) -> Result<(), InterpretResult> {
+        trace!("vm::VM::extend_list()");
+
+        let Some(Value::List { value: list }) = self.peek_value_at(count) else {
+            return Err(self.runtime_error("Invalid access to stack."));
+        };
+
+        let items = self.stack.split_off(self.stack.len() - count);
+        self.heap.get_mut(list).extend(items);
+
+        Ok(())
+    }
+
+    fn spread(&mut self) -> Result<(), InterpretResult> {
+        trace!("vm::VM::spread()");
+        let Some(Value::List { value: items }) = self.pop_value() else {
+            return Err(self.runtime_error("Can only spread lists."));
+        };
+
+        let Some(Value::List { value: list }) = self.peek_value_at(0) else {
+            return Err(self.runtime_error("Invalid access to stack."));
+        };
+
+        // Spreading a list into itself must copy the items first.
+        let items = self.heap.get(items).clone();
+        self.heap.get_mut(list).extend(items);
+
+        Ok(())
+    }
+
+    fn assert(&mut self, constant: Value) -> Result<(), InterpretResult> {
+        trace!("vm::VM::assert()");
+        let Value::String { value: text } = constant else {
+            return Err(self.runtime_error("Cannot get assertion text."));
+        };
+
+        let message = match self.pop_value() {
+            Some(Value::Nil) | None => format!("Assertion failed: {text}"),
+            Some(message) => format!("Assertion failed: {text}: {}", message.display(&self.heap)),
+        };
+
+        // The cursor is already past the assertion, on the next statement.
+        let offset = self.current_frame().offset() - 1;
+        Err(self.runtime_error_at(offset, message.as_str()))
+    }
+
+    /// Replaces the argument list on top of the stack with its elements and
+    /// returns how many there are.
+    fn unpack_args(&mut self) -> Result<usize, InterpretResult> {
+        let Some(Value::List { value: args }) = self.pop_value() else {
+            return Err(self.runtime_error("Invalid access to stack."));
+        };
+
+        let args = self.heap.get(args);
+        self.stack.extend(args.iter().cloned());
+        Ok(args.len())
+    }
+
+    fn call_spread(&mut self) -> Result<(), InterpretResult> {
+        trace!("vm::VM::call_spread()");
+        let arg_count = self.unpack_args()?;
+
+        let value = self
+            .peek_value_at(arg_count)
+            .ok_or_else(|| self.runtime_error("Invalid access to stack."))?;
+
+        if !self.call_value(value, arg_count) {
+            return Err(InterpretResult::RuntimeError);
+        }
+
+        Ok(())
+    }
+
+    fn tail_call_spread(&mut self) -> Result<(), InterpretResult> {
+        trace!("vm::VM::tail_call_spread()");
+        let arg_count = self.unpack_args()?;
+        self.tail_call_value(arg_count)
+    }
+
+    fn class(&mut self, constant: Value) -> Result<(), InterpretResult> {
+        trace!("vm::VM::class()");
+        let Value::String { value: name } = constant else {
+            return Err(self.runtime_error("Invalid name for class."));
+        };
+
+        let class = self.heap.alloc(Class::new(name.to_string()));
+        self.push_value(Value::Class { value: class });
+
+        Ok(())
+    }
+
+    fn method(&mut self, kind: MethodKind, constant: Value) -> Result<(), InterpretResult> {
+        trace!("vm::VM::method(kind: {:?})", kind);
+        let Value::String { value: name } = constant else {
+            return Err(self.runtime_error("Invalid name for method."));
+        };
+
+        let Some(Value::Closure { value: method }) = self.pop_value() else {
+            return Err(self.runtime_error("Invalid method object."));
+        };
+
+        let class = match self.peek_value_at(0) {
+            Some(Value::Class { value: class }) => class,
+            Some(Value::Trait { value: trait_def }) => {
+                self.heap
+                    .get_mut(trait_def)
+                    .methods
+                    .insert(name.to_string(), method);
+                return Ok(());
+            }
+            _ => return Err(self.runtime_error("Methods can only be defined on classes.")),
+        };
+
+        let class = self.heap.get_mut(class);
+        let table = match kind {
+            MethodKind::Method => &mut class.methods,
+            MethodKind::Getter => &mut class.getters,
+            MethodKind::Setter => &mut class.setters,
+            MethodKind::Static => &mut class.static_methods,
+        };
+        table.insert(name.to_string(), method);
+
+        Ok(())
+    }
+
+    fn trait_op(&mut self, constant: Value) -> Result<(), InterpretResult> {
+        trace!("vm::VM::trait_op()");
+        let Value::String { value: name } = constant else {
+            return Err(self.runtime_error("Invalid name for trait."));
+        };
+
+        let trait_def = self.heap.alloc(Trait::new(name.to_string()));
+        self.push_value(Value::Trait { value: trait_def });
+
+        Ok(())
+    }
+
+    fn implement(&mut self) -> Result<(), InterpretResult> {
+        trace!("vm::VM::implement()");
+        let Some(Value::Trait { value: trait_def }) = self.pop_value() else {
+            return Err(self.runtime_error("Classes can only be composed with traits."));
+        };
+
+        let Some(Value::Class { value: class }) = self.peek_value_at(0) else {
+            return Err(self.runtime_error("Traits can only be applied to classes."));
+        };
+
+        let methods = self.heap.get(trait_def).methods.clone();
+
+        for (name, method) in methods {
+            // Only a method mixed in by an earlier trait conflicts; one the
+            // class defines itself overrides every trait.
+            let class_def = self.heap.get(class);
+            let conflict = class_def.find_method(&name).and_then(|current| {
+                class_def
+                    .traits
+                    .iter()
+                    .map(|other| self.heap.get(*other))
+                    .find(|other| other.methods.get(&name) == Some(&current))
+                    .map(|other| other.name.clone())
+            });
+
+            if let Some(other) = conflict {
+                let message = format!(
+                    "Method '{name}' of class {} is defined by both traits {other} and {}.",
+                    self.heap.get(class).name,
+                    self.heap.get(trait_def).name
+                );
+                return Err(self.runtime_error(message.as_str()));
+            }
+
+            // The class's own definition wins over the trait's.
+            self.heap
+                .get_mut(class)
+                .methods
+                .entry(name)
+                .or_insert(method);
+        }
+
+        self.heap.get_mut(class).traits.push(trait_def);
+
+        Ok(())
+    }
+
+    fn is(&mut self) -> Result<(), InterpretResult> {
+        trace!("vm::VM::is()");
+        let (Some(target), Some(value)) = (self.pop_value(), self.pop_value()) else {
+            return Err(self.runtime_error("Invalid access to stack."));
+        };
+
+        let Value::Instance { value: instance } = value else {
+            let result = match target {
+                Value::Class { value: _ } | Value::Trait { value: _ } => false,
+                _ => {
+                    return Err(
+                        self.runtime_error("Right operand of 'is' must be a class or trait.")
+                    );
+                }
+            };
+            self.push_value(Value::Bool { value: result });
+            return Ok(());
+        };
+
+        let class = self.heap.get(instance).class;
+        let result = match target {
+            Value::Class { value: target } => class == target,
+            Value::Trait { value: target } => self.heap.get(class).implements(target),
+            _ => return Err(self.runtime_error("Right operand of 'is' must be a class or trait.")),
+        };
+
+        self.push_value(Value::Bool { value: result });
+
+        Ok(())
+    }
+
+    fn static_field(&mut self, constant: Value) -> Result<(), InterpretResult> {
+        trace!("vm::VM::static_field()");
+        let Value::String { value: name } = constant else {
+            return Err(self.runtime_error("Invalid name for static field."));
+        };
+
+        let (Some(value), Some(Value::Class { value: class })) =
+            (self.pop_value(), self.peek_value_at(0))
+        else {
+            return Err(self.runtime_error("Static fields can only be defined on classes."));
+        };
+
+        self.heap
+            .get_mut(class)
+            .static_fields
+            .insert(name.to_string(), value);
+
+        Ok(())
+    }
+
+    /// Looks up a special method such as `__add__` on the instance sitting
+    /// `distance` slots below the top of the stack.
+    fn find_operator(&self, distance: usize, name: &str) -> Option<Handle<Closure>> {
+        match self.peek_value_at(distance) {
+            Some(Value::Instance { value }) => self.class_of(value).find_method(name),
+            _ => None,
+        }
+    }
+
+    /// Calls the special method `name` when the receiver below the
+    /// `arg_count` operands is an instance defining it. The operands are
+    /// already laid out as a method call, so the receiver becomes `this` and
+    /// the method's result replaces them when it returns.
+    fn invoke_operator(&mut self, name: &str, arg_count: usize) -> Result<bool, InterpretResult> {
+        let Some(method) = self.find_operator(arg_count, name) else {
+            return Ok(false);
+        };
+
+        if !self.call(method, arg_count) {
+            return Err(InterpretResult::RuntimeError);
+        }
+
+        Ok(true)
+    }
+
+    /// Calls the special method `name` on a left-hand instance defining it.
+    /// Otherwise calls `reflected` on a right-hand instance defining that,
+    /// with the operands swapped so the right one becomes `this`, e.g.
+    /// `1 + v` calls `v.__radd__(1)` and `a > v` calls `v.__lt__(a)`.
+    fn invoke_binary_operator(
+        &mut self,
+        name: &str,
+        reflected: &str,
+    ) -> Result<bool, InterpretResult> {
+        if self.invoke_operator(name, 1)? {
+            return Ok(true);
+        }
+
+        if self.find_operator(0, reflected).is_none() {
+            return Ok(false);
+        }
+
+        let len = self.stack.len();
+        self.stack.swap(len - 1, len - 2);
+        self.invoke_operator(reflected, 1)
+    }
+
+    /// Runs `callee` to completion from native code and returns its result.
+    fn call_function(&mut self, callee: Value, args: Vec<Value>) -> Result<Value, InterpretResult> {
+        trace!("vm::VM::call_function(callee: {callee})");
+        let depth = self.frames.len();
+        let arg_count = args.len();
+
+        self.push_value(callee.clone());
+        for arg in args {
+            self.push_value(arg);
+        }
+
+        if !self.call_value(callee, arg_count) {
+            return Err(InterpretResult::RuntimeError);
+        }
+
+        if self.frames.len() > depth {
+            match self.run(depth) {
+                InterpretResult::Ok => {}
+                e => return Err(e),
+            }
+        }
+
+        self.pop_value()
+            .ok_or_else(|| self.runtime_error("Invalid access to stack."))
+    }
+
+    fn match_variant(&mut self, binding_count: usize) -> Result<(), InterpretResult> {
+        trace!("vm::VM::match_variant()");
+
+        let (Some(pattern), Some(scrutinee)) = (self.pop_value(), self.pop_value()) else {
+            return Err(self.runtime_error("Invalid access to stack."));
+        };
+
+        let (enum_def, variant) = match pattern {
+            Value::VariantConstructor { value } => (value.enum_def, value.variant),
+            Value::Variant { value } => (value.enum_def.clone(), value.variant),
+            _ => return Err(self.runtime_error("Case pattern must be an enum variant.")),
+        };
+
+        let arity = enum_def.variants[variant].arity;
+        if arity != binding_count {
+            let message = format!(
+                "Pattern {}.{} binds {} values but the variant has {}.",
+                enum_def.name, enum_def.variants[variant].name, binding_count, arity
+            );
+            return Err(self.runtime_error(message.as_str()));
+        }
+
+        let matched = match scrutinee {
+            Value::Variant { value } => value.is_same_variant(&enum_def, variant),
+            _ => false,
+        };
+
+        self.push_value(Value::Bool { value: matched });
+
+        Ok(())
+    }
+
+    fn destructure(&mut self) -> Result<(), InterpretResult> {
+        trace!("vm::VM::destructure()");
+        let Some(Value::Variant { value }) = self.pop_value() else {
+            return Err(self.runtime_error("Only enum variants can be destructured."));
+        };
+
+        for field in value.payload.iter() {
+            self.push_value(field.clone());
+        }
+
+        Ok(())
+    }
+
+    /// A closure over `function` capturing `captures`, each a local of the
+    /// current frame or an upvalue of the current closure with its index.
+    fn closure(
+        &mut self,
+        constant: Value,
+        captures: &[(bool, usize)],
+    ) -> Result<Value, InterpretResult> {
+        trace!("vm::VM::closure()");
+        let Value::Function { value: function } = constant else {
+            return Err(self.runtime_error("Invalid function object."));
+        };
+
+        let mut closure = Closure::new(function, vec![]);
+        for (local, index) in captures {
+            let upvalue = self.capture(*local, *index)?;
+            closure.upvalues.push(upvalue);
+        }
+
+        Ok(Value::Closure {
+            value: self.heap.alloc(closure),
+        })
+    }
+
+    /// The upvalue a new closure gets for local `index` of the current
+    /// frame, or for upvalue `index` of the current closure.
+    fn capture(&mut self, local: bool, index: usize) -> Result<Handle<Upvalue>, InterpretResult> {
+        if local {
+            return self.capture_upvalue(index);
+        }
+
+        self.current_closure()
+            .upvalues
+            .get(index)
+            .copied()
+            .ok_or_else(|| self.runtime_error("Bad outer upvalue index."))
+    }
+
+    fn capture_upvalue(&mut self, index: usize) -> Result<Handle<Upvalue>, InterpretResult> {
+        trace!("vm::VM::capture_upvalue(index: {index})");
+        let index = self.current_slot_base() + index;
+
+        match self.open_upvalues.binary_search_by_key(&index, |(i, _)| *i) {
+            Ok(pos) => Ok(self.open_upvalues[pos].1),
+            Err(pos) => {
+                let upvalue = self.heap.alloc(Upvalue::new_open(index));
+                self.open_upvalues.insert(pos, (index, upvalue));
+                Ok(upvalue)
+            }
+        }
+    }
+
+    fn set_upvalue(&mut self, slot: usize) -> Result<(), InterpretResult> {
+        trace!("vm::VM::set_upvalue()");
+
+        let value = self
+            .peek_value_at(0)
+            .ok_or_else(|| self.runtime_error("Stack underflow."))?;
+
+        self.store_upvalue(slot, value)
+    }
+
+    fn store_upvalue(&mut self, slot: usize, value: Value) -> Result<(), InterpretResult> {
+        let upvalue = *self
+            .current_closure()
+            .upvalues
+            .get(slot)
+            .ok_or_else(|| self.runtime_error("Bad upvalue slot."))?;
+
+        let upvalue = self.heap.get_mut(upvalue);
+        match upvalue.loc {
+            UpvalueLoc::Open(i) => self.stack.set(i, value),
+            UpvalueLoc::Closed => {
+                upvalue.value = value;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn get_upvalue(&mut self, slot: usize) -> Result<(), InterpretResult> {
+        trace!("vm::VM::get_upvalue()");
+        let value = self.upvalue(slot)?;
+        self.push_value(value);
+
+        Ok(())
+    }
+
+    fn upvalue(&self, slot: usize) -> Result<Value, InterpretResult> {
+        let upvalue = *self
+            .current_closure()
+            .upvalues
+            .get(slot)
+            .ok_or_else(|| self.runtime_error("Bad upvalue slot."))?;
+
+        let upvalue = self.heap.get(upvalue);
+        let value = match upvalue.loc {
+            UpvalueLoc::Open(i) => self
+                .stack
+                .get(i)
+                .ok_or_else(|| self.runtime_error("Stack out of boundary."))?
+                .clone(),
+            UpvalueLoc::Closed => upvalue.value.clone(),
+        };
+
+        Ok(value)
+    }
+
+    /// Closes the upvalue of the local on top of the stack as it goes out of
+    /// scope, and pops the local like `OP_POP` would.
+    fn close_upvalue(&mut self) {
+        trace!("vm::VM::close_upvalue()");
+        if let Some(top) = self.stack.len().checked_sub(1) {
+            self.close_upvalues_at(top);
+        }
+
+        self.pop_value();
+    }
+
+    fn close_upvalues_at(&mut self, slot: usize) {
+        let start = self.open_upvalues.partition_point(|(i, _)| *i < slot);
+        let end = self.open_upvalues.partition_point(|(i, _)| *i <= slot);
+
+        for (index, upvalue) in self.open_upvalues.drain(start..end) {
+            self.heap
+                .get_mut(upvalue)
+                .close(self.stack.get(index).unwrap());
+        }
+    }
+
+    fn close_upvalues_from(&mut self, from: usize) {
+        trace!("vm::VM::close_upvalues(from: {from})");
+
+        let start = self.open_upvalues.partition_point(|(i, _)| *i < from);
+
+        for (index, upvalue) in self.open_upvalues.drain(start..) {
+            self.heap
+                .get_mut(upvalue)
+                .close(self.stack.get(index).unwrap());
+        }
+    }
+}
+
+impl VM {
+    /// Whether the register engine runs the top frame: it does once it has
+    /// started the frame, and starts every frame with register code.
+    fn on_registers(&self) -> bool {
+        let frame = self.current_frame();
+        frame.on_registers || (frame.cursor == 0 && frame.function.register_code.is_some())
+    }
+
+    /// Runs frames on the register engine until the frame stack shrinks
+    /// back to `depth` or the top frame is one for the stack engine. A
+    /// frame's registers are its stack slots, so calls, upvalues and the
+    /// collector find values where the stack engine would keep them.
+    fn dispatch_registers(&mut self, depth: usize) -> InterpretResult {
+        trace!("vm::VM::dispatch_registers(depth: {depth})");
+
+        'frames: loop {
+            let frame = self.current_frame_mut();
+            let function = frame.function.clone();
+            let Some(code) = function.register_code.as_ref() else {
+                return InterpretResult::Ok;
+            };
+            if !frame.on_registers {
+                // Started on the stack engine, which finishes it.
+                if frame.cursor != 0 {
+                    return InterpretResult::Ok;
+                }
+                frame.on_registers = true;
+            }
+
+            let base = frame.slot_base;
+            let mut pc = frame.cursor;
+            let constants = &function.chunk.constants;
+            self.stack.fill(base + code.register_count);
+
+            loop {
+                if self.heap.should_collect(self.args.gc_stress) {
+                    self.collect_garbage();
+                }
+
+                #[cfg(feature = "trace_execution")]
+                {
+                    crate::debug::disassemble_register_instruction(&function, pc);
+                    print!("  Stack - ");
+                    for value in self.stack.iter() {
+                        print!("[ {} ]", value);
+                    }
+                    println!();
+                }
+
+                let instruction = &code.instructions[pc];
+                pc += 1;
+
+                match instruction {
+                    Instruction::Move { dst, src } => {
+                        let value = self.operand(base, constants, *src);
+                        self.stack.set(base + dst, value);
+                    }
+                    Instruction::Unary { op, dst, src } => {
+                        let value = self.operand(base, constants, *src);
+                        let value = match (op, value) {
+                            (UnaryOp::Not, value) => Value::Bool {
+                                value: value.is_falsy(),
+                            },
+                            (UnaryOp::Negate, Value::Number { value }) => {
+                                Value::Number { value: -value }
+                            }
+                            (UnaryOp::Negate, value) => {
+                                self.current_frame_mut().cursor = pc;
+                                let depth = self.frames.len();
+                                self.push_value(value);
+                                try_or_return!(self.negate());
+                                try_or_return!(self.finish_operator(depth));
+                                self.pop_value().unwrap()
+                            }
+                        };
+                        self.stack.set(base + dst, value);
+                    }
+                    Instruction::Binary { op, dst, a, b } => {
+                        let a = self.operand(base, constants, *a);
+                        let b = self.operand(base, constants, *b);
+                        let value = match (&a, &b) {
+                            (Value::Number { value: a }, Value::Number { value: b }) => {
+                                op.numbers(*a, *b)
+                            }
+                            _ => {
+                                self.current_frame_mut().cursor = pc;
+                                match self.binary(*op, a, b) {
+                                    Ok(value) => value,
+                                    Err(e) => return e,
+                                }
+                            }
+                        };
+                        self.stack.set(base + dst, value);
+                    }
+                    Instruction::Jump { target } => pc = *target,
+                    Instruction::JumpIfFalse { cond, target } => {
+                        if self.stack.get(base + cond).unwrap().is_falsy() {
+                            pc = *target;
+                        }
+                    }
+                    Instruction::DefineGlobal { slot, src } => {
+                        let value = self.operand(base, constants, *src);
+                        self.globals.define(*slot, value);
+                    }
+                    Instruction::GetGlobal { dst, slot } => {
+                        self.current_frame_mut().cursor = pc;
+                        let value = match self.global(*slot) {
+                            Ok(value) => value,
+                            Err(e) => return e,
+                        };
+                        self.stack.set(base + dst, value);
+                    }
+                    Instruction::SetGlobal { slot, src } => {
+                        self.current_frame_mut().cursor = pc;
+                        let value = self.operand(base, constants, *src);
+                        try_or_return!(self.store_global(*slot, value));
+                    }
+                    Instruction::GetUpvalue { dst, index } => {
+                        self.current_frame_mut().cursor = pc;
+                        let value = match self.upvalue(*index) {
+                            Ok(value) => value,
+                            Err(e) => return e,
+                        };
+                        self.stack.set(base + dst, value);
+                    }
+                    Instruction::SetUpvalue { index, src } => {
+                        self.current_frame_mut().cursor = pc;
+                        let value = self.operand(base, constants, *src);
+                        try_or_return!(self.store_upvalue(*index, value));
+                    }
+                    Instruction::GetProperty {
+                        dst,
+                        object,
+                        constant,
+                    } => {
+                        self.current_frame_mut().cursor = pc;
+                        let depth = self.frames.len();
+                        let object = self.operand(base, constants, *object);
+                        self.push_value(object);
+                        let value =
+                            match self.property(constants, *constant, depth, Self::get_property_of)
+                            {
+                                Ok(value) => value,
+                                Err(e) => return e,
+                            };
+                        self.stack.set(base + dst, value);
+                    }
+                    Instruction::SetProperty {
+                        dst,
+                        object,
+                        value,
+                        constant,
+                    } => {
+                        self.current_frame_mut().cursor = pc;
+                        let depth = self.frames.len();
+                        let object = self.operand(base, constants, *object);
+                        let value = self.operand(base, constants, *value);
+                        self.push_value(object);
+                        self.push_value(value);
+                        let value =
+                            match self.property(constants, *constant, depth, Self::set_property_of)
+                            {
+                                Ok(value) => value,
+                                Err(e) => return e,
+                            };
+                        self.stack.set(base + dst, value);
+                    }
+                    Instruction::CloseUpvalue { register } => {
+                        self.close_upvalues_at(base + register);
+                    }
+                    Instruction::Closure {
+                        dst,
+                        constant,
+                        captures,
+                    } => {
+                        self.current_frame_mut().cursor = pc;
+                        let value = match self.closure(constants[*constant].clone(), captures) {
+                            Ok(value) => value,
+                            Err(e) => return e,
+                        };
+                        self.stack.set(base + dst, value);
+                    }
+                    Instruction::Call { callee, arg_count } => {
+                        self.current_frame_mut().cursor = pc;
+                        let frame_count = self.frames.len();
+                        self.stack.truncate(base + callee + arg_count + 1);
+                        let value = self.stack.get(base + callee).unwrap();
+                        if !self.call_value(value, *arg_count) {
+                            return InterpretResult::RuntimeError;
+                        }
+
+                        if self.frames.len() > frame_count {
+                            continue 'frames;
+                        }
+                        self.stack.fill(base + code.register_count);
+                    }
+                    Instruction::TailCall { callee, arg_count } => {
+                        self.current_frame_mut().cursor = pc;
+                        let frame_count = self.frames.len();
+                        self.stack.truncate(base + callee + arg_count + 1);
+                        try_or_return!(self.tail_call_value(*arg_count));
+
+                        if self.frames.len() > frame_count {
+                            continue 'frames;
+                        }
+                        // The frame starts over with the callee's code.
+                        if self.current_frame().cursor == 0 {
+                            self.current_frame_mut().on_registers = false;
+                            continue 'frames;
+                        }
+                        self.stack.fill(base + code.register_count);
+                    }
+                    Instruction::Print { start, count } => {
+                        self.current_frame_mut().cursor = pc;
+                        self.stack.truncate(base + start + count);
+                        try_or_return!(self.print_values(
+                            *count,
+                            String::from(" "),
+                            String::from("\n")
+                        ));
+                        self.stack.fill(base + code.register_count);
+                    }
+                    Instruction::Return { src } => {
+                        self.current_frame_mut().cursor = pc;
+                        let value = self.operand(base, constants, *src);
+                        self.push_value(value);
+                        if let Err(e) = self.return_op() {
+                            return e;
+                        }
+
+                        if self.frames.len() == depth {
+                            return InterpretResult::Ok;
+                        }
+                        continue 'frames;
+                    }
+                }
+            }
+        }
+    }
+
+    #[inline]
+    fn operand(&self, base: usize, constants: &[Value], operand: Operand) -> Value {
+        match operand {
+            Operand::Register(register) => self.stack.get(base + register).unwrap(),
+            Operand::Constant(index) => constants[index].clone(),
+            Operand::Nil => Value::Nil,
+            Operand::Bool(value) => Value::Bool { value },
+        }
+    }
+
+    /// Runs `access` for the property named by `constant` on the operands
+    /// pushed for it, then any getter or setter it called, and pops the
+    /// result.
+    fn property(
+        &mut self,
+        constants: &[Value],
+        constant: usize,
+        depth: usize,
+        access: fn(&mut Self, InternedString) -> Result<(), InterpretResult>,
+    ) -> Result<Value, InterpretResult> {
+        let Value::String { value: name } = constants[constant].clone() else {
+            return Err(self.runtime_error("Invalid name for property."));
+        };
+
+        access(self, name)?;
+        self.finish_operator(depth)?;
+        self.pop_value()
+            .ok_or_else(|| self.runtime_error("Invalid access to stack."))
+    }
+
+    /// A binary operator on anything but two numbers, run by the stack
+    /// engine's operator so that strings, operator methods and type errors
+    /// behave the same.
+    fn binary(&mut self, op: BinaryOp, a: Value, b: Value) -> Result<Value, InterpretResult> {
+        let depth = self.frames.len();
+        self.push_value(a);
+        self.push_value(b);
+        match op {
+            BinaryOp::Add => self.add(),
+            BinaryOp::Subtract => self.subtract(),
+            BinaryOp::Multiply => self.multiply(),
+            BinaryOp::Divide => self.divide(),
+            BinaryOp::Equal => self.equal(),
+            BinaryOp::NotEqual => self.negated(Self::equal),
+            BinaryOp::Greater => self.greater(),
+            BinaryOp::GreaterEqual => self.negated(Self::less),
+            BinaryOp::Less => self.less(),
+            BinaryOp::LessEqual => self.negated(Self::greater),
+        }?;
+        self.finish_operator(depth)?;
+
+        self.pop_value()
+            .ok_or_else(|| self.runtime_error("Invalid access to stack."))
+    }
+}
+
+impl VM {
+    /// Marks everything the running program can still reach and frees the
+    /// rest. Only called between instructions, when every live value is
+    /// somewhere in these roots rather than in a Rust local.
+    fn collect_garbage(&mut self) {
+        trace!("vm::VM::collect_garbage()");
+        let before = self.heap.len();
+        let mut marker = self.heap.marker();
+
+        for value in self.stack.iter() {
+            marker.value(&value);
+        }
+        for frame in self.frames.iter() {
+            frame.trace(&mut marker);
+        }
+        for value in self.globals.values() {
+            marker.value(value);
+        }
+        // Closed upvalues are reached through the closures that captured
+        // them, but an open one must survive until it is closed.
+        for (_, upvalue) in self.open_upvalues.iter() {
+            marker.handle(*upvalue);
+        }
+        for (task, value) in self.ready.iter() {
+            marker.task(task);
+            marker.value(value);
+        }
+        for timer in self.timers.iter() {
+            match &timer.action {
+                TimerAction::Resolve(promise) => marker.handle(*promise),
+                TimerAction::Call(callback) => marker.value(callback),
+            }
+        }
+
+        let freed = self.heap.sweep();
+        trace!(
+            "vm::VM::collect_garbage() freed {freed} of {before} objects, {} left, {} strings",
+            self.heap.len(),
+            self.interner.len()
+        );
+    }
+}
+
+impl VM {
+    /// Runs woken tasks and due timers until nothing is left to wait for.
+    fn run_event_loop(&mut self) -> InterpretResult {
+        trace!("vm::VM::run_event_loop()");
+        loop {
+            if let Some((task, value)) = self.ready.pop_front() {
+                if let Err(e) = self.resume(task, value) {
+                    return e;
+                }
+                continue;
+            }
+
+            let Some(index) = self
+                .timers
+                .iter()
+                .enumerate()
+                .min_by(|(_, a), (_, b)| a.due.total_cmp(&b.due).then(a.sequence.cmp(&b.sequence)))
+                .map(|(index, _)| index)
+            else {
+                return InterpretResult::Ok;
+            };
+            let timer = self.timers.remove(index);
+
+            self.wait_until(timer.due);
+
+            match timer.action {
+                TimerAction::Resolve(promise) => self.resolve_promise(promise, Value::Nil),
+                TimerAction::Call(callback) => {
+                    if let Err(e) = self.call_function(callback, vec![]) {
+                        return e;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Puts a suspended task back on top of the stack and runs it until it
+    /// returns or suspends again, with `value` as the result of its `await`.
+    fn resume(&mut self, task: Task, value: Value) -> Result<(), InterpretResult> {
+        trace!("vm::VM::resume(value: {value})");
+        let Task {
+            mut frame,
+            stack,
+            upvalues,
+        } = task;
+
+        let base = self.stack.len();
+        self.stack.extend(stack);
+        for (offset, upvalue) in upvalues {
+            let index = base + offset;
+            let value = self.heap.get_mut(upvalue).reopen(index);
+            self.stack.set(index, value);
+            self.open_upvalues.push((index, upvalue));
+        }
+
+        frame.slot_base = base;
+        self.push_value(value);
+
+        let depth = self.frames.len();
+        self.frames.push(frame);
+        let result = self.run(depth);
+        self.stack.truncate(base);
+
+        match result {
+            InterpretResult::Ok => Ok(()),
+            e => Err(e),
+        }
+    }
+
+    fn resolve_promise(&mut self, promise: Handle<Promise>, value: Value) {
+        trace!("vm::VM::resolve_promise(value: {value})");
+        let resolved = PromiseState::Resolved {
+            value: value.clone(),
+        };
+        let state = std::mem::replace(&mut self.heap.get_mut(promise).state, resolved);
+
+        if let PromiseState::Pending { waiters } = state {
+            for task in waiters {
+                self.ready.push_back((task, value.clone()));
+            }
+        }
+    }
+
+    fn schedule(&mut self, delay: f64, action: TimerAction) {
+        trace!("vm::VM::schedule(delay: {delay})");
+        let due = self.now() + delay.max(0.0);
+        self.timers
+            .push(Timer::new(due, self.timer_sequence, action));
+        self.timer_sequence += 1;
+    }
+
+    /// Milliseconds since the VM started, or the virtual time when running
+    /// with `--virtual-clock`.
+    fn now(&self) -> f64 {
+        if self.args.virtual_clock {
+            self.virtual_time
+        } else {
+            self.started.elapsed().as_secs_f64() * 1000.0
+        }
+    }
+
+    fn wait_until(&mut self, due: f64) {
+        if self.args.virtual_clock {
+            self.virtual_time = self.virtual_time.max(due);
+            return;
+        }
+
+        let remaining = due - self.now();
+        if let Ok(duration) = Duration::try_from_secs_f64(remaining / 1000.0) {
+            thread::sleep(duration);
+        }
+    }
+
+    fn runtime_error(&self, message: &str) -> InterpretResult {
+        self.runtime_error_at(self.current_frame().offset(), message)
+    }
+
+    /// Reports a runtime error at `offset` in the current function instead
+    /// of where its cursor is.
+    fn runtime_error_at(&self, offset: usize, message: &str) -> InterpretResult {
+        let frame = self.current_frame();
+        let loc = frame.function.chunk.loc(offset);
         eprintln!("[{}:{}] {message}", loc.line, loc.col);
 
         for frame in self.frames.iter() {
-            let function = &frame.closure.function;
-            let instruction = frame.cursor - 1;
-            let loc = function.chunk.loc[instruction].clone();
+            let function = &frame.function;
+            let instruction = frame.offset() - 1;
+            let loc = function.chunk.loc(instruction);
             eprint!("[line {}] in ", loc.line);
 
             match &function.name {
@@ -0,0 +1,64 @@
+mod common;
+
+use std::{fs, process::Stdio, thread, time::Duration};
+
+/// Every iteration captures a fresh variable in a fresh closure and drops
+/// both again, so nothing should outlive its iteration.
+const MILLION_CLOSURES: &str = "
+var i = 0;
+while (i < 1000000) {
+  var x = i;
+  fun get() { return x; }
+  get();
+  i = i + 1;
+}
+print i;
+";
+
+/// Peak resident memory allowed for the run. An interpreter that keeps one
+/// upvalue or stack slot per closure needs several times this much.
+const MEMORY_LIMIT_KB: u64 = 40 * 1024;
+
+/// Reads the peak resident set size of a running process from procfs.
+#[cfg(target_os = "linux")]
+fn peak_rss_kb(pid: u32) -> Option<u64> {
+    let status = fs::read_to_string(format!("/proc/{pid}/status")).ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmHWM:"))?;
+    line.split_whitespace().nth(1)?.parse().ok()
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn million_closures_run_in_bounded_memory() {
+    let path = common::write_script("million-closures", MILLION_CLOSURES);
+
+    let mut child = common::interpreter()
+        .arg(&path)
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("failed to start interpreter");
+
+    // The high-water mark only grows, so the last reading before the
+    // process exits is close enough to its peak.
+    let mut peak = 0;
+    while child
+        .try_wait()
+        .expect("failed to poll interpreter")
+        .is_none()
+    {
+        if let Some(rss) = peak_rss_kb(child.id()) {
+            peak = peak.max(rss);
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    let output = child.wait_with_output().expect("failed to read output");
+    fs::remove_file(&path).ok();
+
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), "1000000");
+    assert!(
+        peak < MEMORY_LIMIT_KB,
+        "peak memory was {peak} KB, limit is {MEMORY_LIMIT_KB} KB"
+    );
+}
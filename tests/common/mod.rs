@@ -0,0 +1,51 @@
+//! Helpers shared by the integration tests. Each test binary uses only some
+//! of them.
+#![allow(dead_code)]
+
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process::{self, Command, Output},
+};
+
+/// Writes `source` to a script in the temp directory. `name` keeps the
+/// scripts of tests running in parallel apart.
+pub fn write_script(name: &str, source: &str) -> PathBuf {
+    let path = env::temp_dir().join(format!("lox-{name}-{}.lox", process::id()));
+    fs::write(&path, source).expect("failed to write test script");
+    path
+}
+
+/// The regression scripts in `tests/scripts`, in name order.
+pub fn scripts() -> Vec<PathBuf> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/scripts");
+    let mut scripts: Vec<_> = fs::read_dir(dir)
+        .expect("failed to list test scripts")
+        .map(|entry| entry.expect("failed to read test scripts").path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "lox"))
+        .collect();
+    scripts.sort();
+    scripts
+}
+
+/// A command that runs the interpreter built for these tests.
+pub fn interpreter() -> Command {
+    Command::new(env!("CARGO_BIN_EXE_lox-rust-2"))
+}
+
+/// Runs the script at `path` with `flags` and waits for it to exit.
+pub fn run_file(path: &Path, flags: &[&str]) -> Output {
+    interpreter()
+        .args(flags)
+        .arg(path)
+        .output()
+        .expect("failed to run interpreter")
+}
+
+/// Runs `source` with `flags` and waits for it to exit.
+pub fn run(name: &str, source: &str, flags: &[&str]) -> Output {
+    let path = write_script(name, source);
+    let output = run_file(&path, flags);
+    fs::remove_file(&path).ok();
+    output
+}
@@ -0,0 +1,50 @@
+//! Runs the regression scripts in `tests/scripts` on the stack engine and
+//! on the register engine. Both must print the same output, report the
+//! same errors and exit with the same status.
+
+mod common;
+
+use std::{path::Path, process::Output};
+
+fn run_on(engine: &str, script: &Path, flags: &[&str]) -> Output {
+    let flags = [&["--virtual-clock", "--engine", engine], flags].concat();
+    common::run_file(script, &flags)
+}
+
+/// Checks every script with `flags` on both engines and reports all whose
+/// runs differ.
+fn check_engines_agree(flags: &[&str]) {
+    let mut failures = vec![];
+
+    for script in common::scripts() {
+        let stack = run_on("stack", &script, flags);
+        let register = run_on("register", &script, flags);
+
+        if stack.stdout != register.stdout
+            || stack.stderr != register.stderr
+            || stack.status.code() != register.status.code()
+        {
+            failures.push(format!(
+                "{}:\nstack: {stack:?}\nregister: {register:?}",
+                script.display()
+            ));
+        }
+    }
+
+    assert!(
+        failures.is_empty(),
+        "{} scripts differ between engines with {flags:?}:\n{}",
+        failures.len(),
+        failures.join("\n")
+    );
+}
+
+#[test]
+fn engines_agree() {
+    check_engines_agree(&[]);
+}
+
+#[test]
+fn engines_agree_under_gc_stress() {
+    check_engines_agree(&["--gc-stress"]);
+}
@@ -0,0 +1,67 @@
+mod common;
+
+/// A number on the left hands the operator to the instance on the right
+/// through its reflected method.
+const RIGHT_HAND_ARITHMETIC: &str = r#"
+class V {
+  init(x) { this.x = x; }
+  __radd__(left) { return left + this.x; }
+  __rsub__(left) { return left - this.x; }
+  __rmul__(left) { return left * this.x; }
+  __rdiv__(left) { return left / this.x; }
+}
+var v = V(4);
+print 1 + v, 10 - v, 3 * v, 2 / v;
+"#;
+
+/// A class defining only `__lt__` gets the other comparisons by mirroring
+/// and negating it.
+const ONLY_LESS: &str = r#"
+class N {
+  init(n) { this.n = n; }
+  __lt__(other) { return this.n < other.n; }
+}
+var one = N(1);
+var two = N(2);
+print one < two, one > two, one <= two, one >= two;
+print two < one, two > one, two <= one, two >= one;
+print one <= one, one >= one;
+"#;
+
+/// Without a reflected method the operands are still rejected.
+const NO_REFLECTED_METHOD: &str = "
+class V {}
+print 1 + V();
+";
+
+fn stdout(name: &str, source: &str) -> String {
+    let output = common::run(name, source, &[]);
+    assert!(output.status.success(), "{name} failed: {output:?}");
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+#[test]
+fn right_hand_instances_use_reflected_methods() {
+    assert_eq!(
+        stdout("right-hand-arithmetic", RIGHT_HAND_ARITHMETIC),
+        "5 6 12 0.5\n"
+    );
+}
+
+#[test]
+fn comparisons_mirror_to_the_right_hand_method() {
+    assert_eq!(
+        stdout("only-less", ONLY_LESS),
+        "true false true false\nfalse true false true\ntrue true\n"
+    );
+}
+
+#[test]
+fn operands_without_methods_are_still_errors() {
+    let output = common::run("no-reflected-method", NO_REFLECTED_METHOD, &[]);
+    assert_eq!(output.status.code(), Some(70));
+    assert!(
+        String::from_utf8_lossy(&output.stderr)
+            .contains("Operands must be two numbers or two strings.")
+    );
+}
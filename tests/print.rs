@@ -0,0 +1,53 @@
+mod common;
+
+/// `sep` and `end` are only print options after a value and a comma. In
+/// first position they are plain variables being assigned.
+const ASSIGN_OPTION_NAMES: &str = r#"
+var sep = 1;
+var end = 1;
+print sep = 5;
+print sep;
+print end = 2, end;
+print 1, 2, sep = "-", end = "!";
+"#;
+
+#[test]
+fn option_names_can_be_assigned_in_print() {
+    let output = common::run("print-options", ASSIGN_OPTION_NAMES, &[]);
+    assert!(output.status.success(), "script failed: {output:?}");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "5\n5\n2 2\n1-2!");
+}
+
+#[test]
+fn oversized_format_widths_are_errors() {
+    for (name, spec) in [
+        ("format-width", "{:1001}"),
+        ("format-overflowing-width", "{:99999999999999999999999}"),
+        ("format-precision", "{:.1001}"),
+    ] {
+        let source = format!("print format(\"{spec}\", 1);");
+        let output = common::run(name, &source, &[]);
+
+        assert_eq!(output.status.code(), Some(70), "{spec}: {output:?}");
+        assert!(String::from_utf8_lossy(&output.stderr).contains("is larger than 1000"));
+    }
+}
+
+/// `format` renders instances through `toString` like `print`, and the `0`
+/// flag zero-pads numbers whatever the alignment, as Rust's `format!` does.
+const FORMAT_LIKE_PRINT: &str = r#"
+class V { toString() { return "vee"; } }
+print format("{} {:>5}|", V(), V()), V();
+print format("{:^06} {:<+06} {:*>06} {:06}|", 3, 3, -3, "ab");
+"#;
+
+#[test]
+fn format_renders_values_like_print() {
+    let output = common::run("format-like-print", FORMAT_LIKE_PRINT, &[]);
+
+    assert!(output.status.success(), "script failed: {output:?}");
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "vee   vee| vee\n000003 +00003 -00003 ab    |\n"
+    );
+}
@@ -0,0 +1,68 @@
+//! Runs the regression scripts in `tests/scripts` and compares what they
+//! print with the `.out` and `.err` files next to them. A script with an
+//! `.err` file is expected to fail, and one without it to succeed. Timers
+//! use the virtual clock so the output doesn't depend on the machine.
+//!
+//! Run `cargo test --features nan_boxing` to check the NaN-boxed build
+//! against the same expectations.
+
+mod common;
+
+use std::{fs, path::Path};
+
+/// Returns how the script's output differs from its expectations, if it
+/// does.
+fn mismatch(script: &Path, flags: &[&str]) -> Option<String> {
+    let flags = [&["--virtual-clock"], flags].concat();
+    let output = common::run_file(script, &flags);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    let expected_stdout = fs::read_to_string(script.with_extension("out")).unwrap_or_default();
+    let expected_stderr = fs::read_to_string(script.with_extension("err")).unwrap_or_default();
+
+    if stdout != expected_stdout {
+        return Some(format!("stdout was\n{stdout}"));
+    }
+    if stderr != expected_stderr {
+        return Some(format!("stderr was\n{stderr}"));
+    }
+    if output.status.success() != expected_stderr.is_empty() {
+        return Some(format!("exited with {}", output.status));
+    }
+    None
+}
+
+/// Checks every script with `flags` and reports all that don't match.
+fn check_scripts(flags: &[&str]) {
+    let failures: Vec<_> = common::scripts()
+        .iter()
+        .filter_map(|script| {
+            mismatch(script, flags).map(|problem| format!("{}: {problem}", script.display()))
+        })
+        .collect();
+
+    assert!(
+        failures.is_empty(),
+        "{} scripts failed with {flags:?}:\n{}",
+        failures.len(),
+        failures.join("\n")
+    );
+}
+
+#[test]
+fn scripts_print_expected_output() {
+    check_scripts(&[]);
+}
+
+/// Collecting before every instruction that follows an allocation catches
+/// values the VM forgot to root.
+#[test]
+fn scripts_print_expected_output_under_gc_stress() {
+    check_scripts(&["--gc-stress"]);
+}
+
+#[test]
+fn scripts_print_expected_output_without_optimizations() {
+    check_scripts(&["-O0"]);
+}
@@ -0,0 +1,26 @@
+mod common;
+
+/// Recurses far deeper than the frame limit through calls that spread
+/// their arguments, so each one has to reuse its caller's frame.
+const DEEP_SPREAD_RECURSION: &str = "
+fun count(n, total) {
+  if (n == 0) return total;
+  var args = [n - 1, total + 1];
+  return count(...args);
+}
+print count(100000, 0);
+";
+
+#[test]
+fn spread_calls_in_return_position_are_tail_calls() {
+    for engine in ["stack", "register"] {
+        let output = common::run(
+            "deep-spread-recursion",
+            DEEP_SPREAD_RECURSION,
+            &["--engine", engine],
+        );
+
+        assert!(output.status.success(), "{engine}: {output:?}");
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "100000\n");
+    }
+}
@@ -0,0 +1,27 @@
+mod common;
+
+/// Each delay is too long to sleep for, so every call must fail as a Lox
+/// runtime error rather than panic when the event loop converts it.
+const TOO_LONG: &[(&str, &str)] = &[
+    (
+        "infinite-sleep",
+        "async fun main() { await sleep(1/0); } main();",
+    ),
+    ("huge-sleep", "sleep(1e308);"),
+    ("nan-sleep", "sleep(0/0);"),
+    ("huge-timeout", "fun f() {} setTimeout(f, 1e308);"),
+];
+
+#[test]
+fn delays_too_long_to_sleep_are_runtime_errors() {
+    for (name, source) in TOO_LONG {
+        let output = common::run(name, source, &[]);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+
+        assert_eq!(output.status.code(), Some(70), "{name}: {output:?}");
+        assert!(
+            stderr.contains("expects a finite delay"),
+            "{name}: {stderr}"
+        );
+    }
+}